@@ -24,30 +24,18 @@ use sc_chain_spec::Properties;
 #[rpc(client, server)]
 pub trait ChainSpecApi {
 	/// Get the chain name, as present in the chain specification.
-	///
-	/// # Unstable
-	///
-	/// This method is unstable and subject to change in the future.
-	#[method(name = "chainSpec_unstable_chainName")]
-	fn chain_spec_unstable_chain_name(&self) -> RpcResult<String>;
+	#[method(name = "chainSpec_v1_chainName")]
+	fn chain_spec_v1_chain_name(&self) -> RpcResult<String>;
 
 	/// Get the chain's genesis hash.
-	///
-	/// # Unstable
-	///
-	/// This method is unstable and subject to change in the future.
-	#[method(name = "chainSpec_unstable_genesisHash")]
-	fn chain_spec_unstable_genesis_hash(&self) -> RpcResult<String>;
+	#[method(name = "chainSpec_v1_genesisHash")]
+	fn chain_spec_v1_genesis_hash(&self) -> RpcResult<String>;
 
 	/// Get the properties of the chain, as present in the chain specification.
 	///
 	/// # Note
 	///
 	/// The json whitespaces are not guaranteed to persist.
-	///
-	/// # Unstable
-	///
-	/// This method is unstable and subject to change in the future.
-	#[method(name = "chainSpec_unstable_properties")]
-	fn chain_spec_unstable_properties(&self) -> RpcResult<Properties>;
+	#[method(name = "chainSpec_v1_properties")]
+	fn chain_spec_v1_properties(&self) -> RpcResult<Properties>;
 }
@@ -46,15 +46,15 @@ impl ChainSpec {
 }
 
 impl ChainSpecApiServer for ChainSpec {
-	fn chain_spec_unstable_chain_name(&self) -> RpcResult<String> {
+	fn chain_spec_v1_chain_name(&self) -> RpcResult<String> {
 		Ok(self.name.clone())
 	}
 
-	fn chain_spec_unstable_genesis_hash(&self) -> RpcResult<String> {
+	fn chain_spec_v1_genesis_hash(&self) -> RpcResult<String> {
 		Ok(self.genesis_hash.clone())
 	}
 
-	fn chain_spec_unstable_properties(&self) -> RpcResult<Properties> {
+	fn chain_spec_v1_properties(&self) -> RpcResult<Properties> {
 		Ok(self.properties.clone())
 	}
 }
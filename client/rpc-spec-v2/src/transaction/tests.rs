@@ -0,0 +1,378 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+use assert_matches::assert_matches;
+use codec::Encode;
+use futures::{channel::mpsc, future::BoxFuture, Future, StreamExt};
+use jsonrpsee::{
+	core::error::Error,
+	types::error::{CallError, INVALID_PARAMS_CODE},
+	RpcModule,
+};
+use parking_lot::Mutex;
+use sc_block_builder::BlockBuilderProvider;
+use sc_transaction_pool_api::{
+	error::Error as PoolError, ImportNotificationStream, InPoolTransaction, PoolFuture, PoolStatus,
+	ReadyTransactions, TransactionFor, TransactionLongevity, TransactionPool, TransactionPriority,
+	TransactionSource, TransactionStatus, TransactionStatusStreamFor, TransactionTag, TxHash,
+};
+use sp_blockchain::HeaderBackend;
+use sp_consensus::BlockOrigin;
+use sp_core::{traits::SpawnNamed, H256};
+use sp_runtime::{generic::BlockId, traits::NumberFor};
+use std::{
+	collections::{HashMap, VecDeque},
+	pin::Pin,
+	sync::Arc,
+	time::Duration,
+};
+use substrate_test_runtime_client::{prelude::*, runtime, Backend, Client, ClientBlockImportExt};
+
+type Block = runtime::Block;
+type TxStatus = TransactionStatus<H256, H256>;
+type Broadcast = TransactionBroadcast<MockPool, Client<Backend>>;
+
+/// The response of the [`MockPool`] to a submission.
+type Response = Result<Vec<TxStatus>, PoolError>;
+
+async fn run_with_timeout<F: Future>(future: F) -> <F as Future>::Output {
+	tokio::time::timeout(Duration::from_secs(60), future).await.unwrap()
+}
+
+/// A transaction pool answering the submissions with scripted responses.
+struct MockPool {
+	/// The responses to the next submissions, in order.
+	///
+	/// Once they are exhausted, the submissions are watched by a stream that never ends.
+	responses: Mutex<VecDeque<Response>>,
+	/// Reports the block at which each transaction is submitted.
+	submissions: mpsc::UnboundedSender<H256>,
+}
+
+impl MockPool {
+	fn new(responses: Vec<Response>) -> (Arc<Self>, mpsc::UnboundedReceiver<H256>) {
+		let (submissions, receiver) = mpsc::unbounded();
+		(Arc::new(MockPool { responses: Mutex::new(responses.into()), submissions }), receiver)
+	}
+}
+
+/// The in-pool transactions are never handed out by the [`MockPool`].
+struct MockInPoolTransaction;
+
+impl InPoolTransaction for MockInPoolTransaction {
+	type Transaction = runtime::Extrinsic;
+	type Hash = H256;
+
+	fn data(&self) -> &Self::Transaction {
+		unimplemented!()
+	}
+
+	fn hash(&self) -> &Self::Hash {
+		unimplemented!()
+	}
+
+	fn priority(&self) -> &TransactionPriority {
+		unimplemented!()
+	}
+
+	fn longevity(&self) -> &TransactionLongevity {
+		unimplemented!()
+	}
+
+	fn requires(&self) -> &[TransactionTag] {
+		unimplemented!()
+	}
+
+	fn provides(&self) -> &[TransactionTag] {
+		unimplemented!()
+	}
+
+	fn is_propagable(&self) -> bool {
+		unimplemented!()
+	}
+}
+
+impl TransactionPool for MockPool {
+	type Block = Block;
+	type Hash = H256;
+	type InPoolTransaction = MockInPoolTransaction;
+	type Error = PoolError;
+
+	fn submit_and_watch(
+		&self,
+		at: &BlockId<Self::Block>,
+		_source: TransactionSource,
+		_xt: TransactionFor<Self>,
+	) -> PoolFuture<Pin<Box<TransactionStatusStreamFor<Self>>>, Self::Error> {
+		let BlockId::Hash(at) = at else { panic!("transactions are submitted at a block hash") };
+		self.submissions.unbounded_send(*at).unwrap();
+
+		let response = self.responses.lock().pop_front().unwrap_or_else(|| Ok(vec![]));
+		let response = response
+			.map(|events| futures::stream::iter(events).chain(futures::stream::pending()).boxed());
+		Box::pin(futures::future::ready(response))
+	}
+
+	// The following methods are not used by the broadcast.
+
+	fn submit_at(
+		&self,
+		_at: &BlockId<Self::Block>,
+		_source: TransactionSource,
+		_xts: Vec<TransactionFor<Self>>,
+	) -> PoolFuture<Vec<Result<TxHash<Self>, Self::Error>>, Self::Error> {
+		unimplemented!()
+	}
+
+	fn submit_one(
+		&self,
+		_at: &BlockId<Self::Block>,
+		_source: TransactionSource,
+		_xt: TransactionFor<Self>,
+	) -> PoolFuture<TxHash<Self>, Self::Error> {
+		unimplemented!()
+	}
+
+	fn ready_at(
+		&self,
+		_at: NumberFor<Self::Block>,
+	) -> Pin<
+		Box<
+			dyn Future<
+					Output = Box<dyn ReadyTransactions<Item = Arc<Self::InPoolTransaction>> + Send>,
+				> + Send,
+		>,
+	> {
+		unimplemented!()
+	}
+
+	fn ready(&self) -> Box<dyn ReadyTransactions<Item = Arc<Self::InPoolTransaction>> + Send> {
+		unimplemented!()
+	}
+
+	fn remove_invalid(&self, _hashes: &[TxHash<Self>]) -> Vec<Arc<Self::InPoolTransaction>> {
+		unimplemented!()
+	}
+
+	fn status(&self) -> PoolStatus {
+		unimplemented!()
+	}
+
+	fn import_notification_stream(&self) -> ImportNotificationStream<TxHash<Self>> {
+		unimplemented!()
+	}
+
+	fn on_broadcasted(&self, _propagations: HashMap<TxHash<Self>, Vec<String>>) {
+		unimplemented!()
+	}
+
+	fn hash_of(&self, _xt: &TransactionFor<Self>) -> TxHash<Self> {
+		unimplemented!()
+	}
+
+	fn ready_transaction(&self, _hash: &TxHash<Self>) -> Option<Arc<Self::InPoolTransaction>> {
+		unimplemented!()
+	}
+}
+
+/// Spawns the broadcasts on the tokio runtime of the test and keeps their handles, such that
+/// the tests can wait for a broadcast to end.
+#[derive(Clone, Default)]
+struct MockExecutor {
+	handles: Arc<Mutex<Vec<tokio::task::JoinHandle<()>>>>,
+}
+
+impl MockExecutor {
+	/// Wait until the most recently spawned task ends.
+	async fn wait_for_last_task(&self) {
+		let handle = self.handles.lock().pop().expect("a task was spawned");
+		run_with_timeout(handle).await.unwrap();
+	}
+}
+
+impl SpawnNamed for MockExecutor {
+	fn spawn_blocking(
+		&self,
+		_name: &'static str,
+		_group: Option<&'static str>,
+		_future: BoxFuture<'static, ()>,
+	) {
+		unimplemented!()
+	}
+
+	fn spawn(
+		&self,
+		_name: &'static str,
+		_group: Option<&'static str>,
+		future: BoxFuture<'static, ()>,
+	) {
+		self.handles.lock().push(tokio::spawn(future));
+	}
+}
+
+fn setup_api(
+	responses: Vec<Response>,
+) -> (Arc<Client<Backend>>, RpcModule<Broadcast>, mpsc::UnboundedReceiver<H256>, Arc<MockExecutor>)
+{
+	let client = Arc::new(substrate_test_runtime_client::new());
+	let (pool, submissions) = MockPool::new(responses);
+	let executor = Arc::new(MockExecutor::default());
+
+	let api = TransactionBroadcast::new(client.clone(), pool, executor.clone()).into_rpc();
+
+	(client, api, submissions, executor)
+}
+
+fn transfer_hex() -> String {
+	let xt = runtime::Transfer {
+		from: AccountKeyring::Alice.into(),
+		to: AccountKeyring::Bob.into(),
+		amount: 42,
+		nonce: 0,
+	}
+	.into_unchecked_extrinsic();
+
+	format!("0x{}", hex::encode(xt.encode()))
+}
+
+/// Import a new best block and return its hash.
+async fn import_best_block(client: &mut Arc<Client<Backend>>) -> H256 {
+	let block = client.new_block(Default::default()).unwrap().build().unwrap().block;
+	let hash = block.header.hash();
+	client.import(BlockOrigin::Own, block).await.unwrap();
+	hash
+}
+
+async fn stop_broadcast(api: &RpcModule<Broadcast>, operation_id: &str) -> Result<(), Error> {
+	api.call("transaction_unstable_stop", [operation_id]).await
+}
+
+#[tokio::test]
+async fn tx_broadcast_resubmits_on_new_best_block() {
+	let (mut client, api, mut submissions, executor) = setup_api(vec![
+		Ok(vec![TxStatus::Ready, TxStatus::Dropped]),
+		Err(PoolError::ImmediatelyDropped),
+	]);
+	let genesis = client.info().genesis_hash;
+
+	let operation_id: Option<String> =
+		api.call("transaction_unstable_broadcast", [transfer_hex()]).await.unwrap();
+	let operation_id = operation_id.unwrap();
+	assert_eq!(run_with_timeout(submissions.next()).await, Some(genesis));
+
+	// The transaction is dropped from the pool and submitted again on the next best block.
+	let block_1 = import_best_block(&mut client).await;
+	assert_eq!(run_with_timeout(submissions.next()).await, Some(block_1));
+
+	// The pool is full and rejects the transaction, which is retried on the next best block.
+	let block_2 = import_best_block(&mut client).await;
+	assert_eq!(run_with_timeout(submissions.next()).await, Some(block_2));
+
+	stop_broadcast(&api, &operation_id).await.unwrap();
+	executor.wait_for_last_task().await;
+}
+
+#[tokio::test]
+async fn tx_broadcast_stop_with_valid_id() {
+	let (client, api, mut submissions, executor) = setup_api(vec![]);
+
+	let operation_id: Option<String> =
+		api.call("transaction_unstable_broadcast", [transfer_hex()]).await.unwrap();
+	let operation_id = operation_id.unwrap();
+	assert_eq!(run_with_timeout(submissions.next()).await, Some(client.info().genesis_hash));
+
+	stop_broadcast(&api, &operation_id).await.unwrap();
+	// The broadcast is aborted and never submits the transaction again.
+	executor.wait_for_last_task().await;
+	assert_matches!(submissions.try_next(), Err(_));
+
+	// The operation ID is no longer valid.
+	let err = stop_broadcast(&api, &operation_id).await.unwrap_err();
+	assert_matches!(
+		err,
+		Error::Call(CallError::Custom(ref err)) if err.code() == INVALID_PARAMS_CODE && err.message() == "Invalid operation id"
+	);
+}
+
+#[tokio::test]
+async fn tx_broadcast_stop_with_unknown_id() {
+	let (_client, api, _submissions, _executor) = setup_api(vec![]);
+
+	let err = stop_broadcast(&api, "unknown").await.unwrap_err();
+	assert_matches!(
+		err,
+		Error::Call(CallError::Custom(ref err)) if err.code() == INVALID_PARAMS_CODE && err.message() == "Invalid operation id"
+	);
+}
+
+#[tokio::test]
+async fn tx_broadcast_ignores_undecodable_bytes() {
+	let (_client, api, mut submissions, executor) = setup_api(vec![]);
+
+	let operation_id: Option<String> =
+		api.call("transaction_unstable_broadcast", ["0x"]).await.unwrap();
+	assert_eq!(operation_id, None);
+
+	// Nothing is submitted to the pool.
+	assert!(executor.handles.lock().is_empty());
+	assert_matches!(submissions.try_next(), Err(_));
+}
+
+#[tokio::test]
+async fn tx_broadcast_ends_in_a_final_state() {
+	let finalized = TxStatus::Finalized((H256::repeat_byte(1), 0));
+	for final_status in [finalized, TxStatus::Invalid] {
+		let (_client, api, mut submissions, executor) =
+			setup_api(vec![Ok(vec![TxStatus::Ready, final_status])]);
+
+		let operation_id: Option<String> =
+			api.call("transaction_unstable_broadcast", [transfer_hex()]).await.unwrap();
+		let operation_id = operation_id.unwrap();
+
+		// The broadcast ends without waiting for a new best block.
+		executor.wait_for_last_task().await;
+		assert!(run_with_timeout(submissions.next()).await.is_some());
+		assert_matches!(submissions.try_next(), Err(_));
+
+		// The operation ID is released once the broadcast ends.
+		let err = stop_broadcast(&api, &operation_id).await.unwrap_err();
+		assert_matches!(
+			err,
+			Error::Call(CallError::Custom(ref err)) if err.code() == INVALID_PARAMS_CODE && err.message() == "Invalid operation id"
+		);
+	}
+}
+
+#[tokio::test]
+async fn tx_broadcast_generates_unguessable_ids() {
+	let (_client, api, _submissions, _executor) = setup_api(vec![]);
+
+	let mut operation_ids = Vec::new();
+	for _ in 0..2 {
+		let operation_id: Option<String> =
+			api.call("transaction_unstable_broadcast", [transfer_hex()]).await.unwrap();
+		operation_ids.push(operation_id.unwrap());
+	}
+
+	assert_ne!(operation_ids[0], operation_ids[1]);
+	for operation_id in operation_ids {
+		assert_eq!(operation_id.len(), 16);
+		assert!(operation_id.chars().all(|c| c.is_ascii_alphanumeric()));
+	}
+}
@@ -0,0 +1,177 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! API implementation for broadcasting transactions.
+
+use crate::{
+	transaction::{api::TransactionBroadcastApiServer, error::ErrorBroadcast},
+	SubscriptionTaskExecutor,
+};
+use codec::Decode;
+use futures::{FutureExt, StreamExt};
+use jsonrpsee::core::RpcResult;
+use parking_lot::RwLock;
+use rand::{distributions::Alphanumeric, Rng};
+use sc_client_api::BlockchainEvents;
+use sc_transaction_pool_api::{
+	error::IntoPoolError, TransactionFor, TransactionPool, TransactionSource,
+};
+use sp_blockchain::HeaderBackend;
+use sp_core::Bytes;
+use sp_runtime::generic;
+use std::{collections::HashMap, sync::Arc};
+
+/// Currently we treat all RPC transactions as externals.
+const TX_SOURCE: TransactionSource = TransactionSource::External;
+
+/// The length of the operation IDs.
+const OPERATION_ID_LEN: usize = 16;
+
+/// An API for transaction broadcast RPC calls.
+pub struct TransactionBroadcast<Pool, Client> {
+	/// Substrate client.
+	client: Arc<Client>,
+	/// Transactions pool.
+	pool: Arc<Pool>,
+	/// Executor to spawn subscriptions.
+	executor: SubscriptionTaskExecutor,
+	/// The broadcast operation IDs.
+	broadcast_ids: Arc<RwLock<HashMap<String, BroadcastState>>>,
+}
+
+/// The state of a broadcast operation.
+struct BroadcastState {
+	/// Handle to abort the running future that broadcasts the transaction.
+	handle: futures::future::AbortHandle,
+}
+
+impl<Pool, Client> TransactionBroadcast<Pool, Client> {
+	/// Creates a new [`TransactionBroadcast`].
+	pub fn new(client: Arc<Client>, pool: Arc<Pool>, executor: SubscriptionTaskExecutor) -> Self {
+		TransactionBroadcast { client, pool, executor, broadcast_ids: Default::default() }
+	}
+
+	/// Generate an unique operation ID for the `transaction_unstable_broadcast` RPC method.
+	///
+	/// The IDs are random, such that a client cannot guess and stop the broadcasts of others.
+	fn generate_unique_id(&self) -> String {
+		let generate_operation_id = || {
+			rand::thread_rng()
+				.sample_iter(Alphanumeric)
+				.take(OPERATION_ID_LEN)
+				.map(char::from)
+				.collect::<String>()
+		};
+
+		let broadcast_ids = self.broadcast_ids.read();
+		let mut id = generate_operation_id();
+		while broadcast_ids.contains_key(&id) {
+			id = generate_operation_id();
+		}
+
+		id
+	}
+}
+
+impl<Pool, Client> TransactionBroadcastApiServer for TransactionBroadcast<Pool, Client>
+where
+	Pool: TransactionPool + Sync + Send + 'static,
+	Client: HeaderBackend<Pool::Block> + BlockchainEvents<Pool::Block> + Send + Sync + 'static,
+{
+	fn broadcast(&self, bytes: Bytes) -> RpcResult<Option<String>> {
+		// The spec does not report any error for the broadcast, an extrinsic
+		// that cannot be decoded is simply never broadcasted.
+		let Ok(decoded_extrinsic) = TransactionFor::<Pool>::decode(&mut &bytes[..]) else {
+			return Ok(None)
+		};
+
+		let id = self.generate_unique_id();
+		let client = self.client.clone();
+		let pool = self.pool.clone();
+
+		let broadcast_transaction_fut = async move {
+			loop {
+				// Subscribe before submitting to not miss any best block imported in between.
+				let mut best_imports = client
+					.import_notification_stream()
+					.filter(|notification| futures::future::ready(notification.is_new_best));
+
+				let best_block_hash = client.info().best_hash;
+				let submit = pool
+					.submit_and_watch(
+						&generic::BlockId::hash(best_block_hash),
+						TX_SOURCE,
+						decoded_extrinsic.clone(),
+					)
+					.await;
+
+				match submit {
+					Ok(mut stream) => {
+						// Wait until the transaction reaches a final state.
+						let mut last_event = None;
+						while let Some(event) = stream.next().await {
+							if event.is_final() {
+								last_event = Some(event);
+								break
+							}
+						}
+
+						// A stream that ended without a final state is treated as
+						// dropped and the transaction is submitted again.
+						if matches!(last_event, Some(event) if !event.is_retriable()) {
+							return
+						}
+					},
+					Err(err) => match err.into_pool_error() {
+						Ok(err) if err.is_retriable() => (),
+						// The transaction can never be included in a block.
+						_ => return,
+					},
+				}
+
+				// Broadcast the transaction again once a new best block is imported.
+				if best_imports.next().await.is_none() {
+					return
+				}
+			}
+		};
+
+		let (fut, handle) = futures::future::abortable(broadcast_transaction_fut);
+		self.broadcast_ids.write().insert(id.clone(), BroadcastState { handle });
+
+		let broadcast_ids = self.broadcast_ids.clone();
+		let drop_id = id.clone();
+		let fut = fut.map(move |_| {
+			// The broadcast ended on its own or was stopped, release the operation ID.
+			broadcast_ids.write().remove(&drop_id);
+		});
+
+		self.executor.spawn("substrate-rpc-subscription", Some("rpc"), fut.boxed());
+
+		Ok(Some(id))
+	}
+
+	fn stop_broadcast(&self, operation_id: String) -> RpcResult<()> {
+		let Some(broadcast_state) = self.broadcast_ids.write().remove(&operation_id) else {
+			return Err(ErrorBroadcast::InvalidOperationID.into())
+		};
+
+		broadcast_state.handle.abort();
+		Ok(())
+	}
+}
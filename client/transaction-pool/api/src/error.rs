@@ -71,6 +71,23 @@ pub enum Error {
 	RejectedFutureTransaction,
 }
 
+impl Error {
+	/// Returns true if the transaction could be re-submitted to the pool in the future.
+	pub fn is_retriable(&self) -> bool {
+		matches!(
+			self,
+			// A banned transaction can be submitted again once the ban expires.
+			Error::TemporarilyBanned |
+				// The pool is full at the moment.
+				Error::ImmediatelyDropped |
+				// The block is not known to the pool yet, e.g. the node is syncing.
+				Error::InvalidBlockId(_) |
+				// The pool doesn't accept future transactions at the moment.
+				Error::RejectedFutureTransaction
+		)
+	}
+}
+
 /// Transaction pool error conversion.
 pub trait IntoPoolError: std::error::Error + Send + Sized + Sync {
 	/// Try to extract original `Error`
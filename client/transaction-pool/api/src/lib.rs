@@ -133,6 +133,35 @@ pub enum TransactionStatus<Hash, BlockHash> {
 	Invalid,
 }
 
+impl<Hash, BlockHash> TransactionStatus<Hash, BlockHash> {
+	/// Returns true if this is the last event emitted by the [`TransactionStatusStream`].
+	pub fn is_final(&self) -> bool {
+		matches!(
+			self,
+			Self::Usurped(_) |
+				Self::Finalized(_) |
+				Self::FinalityTimeout(_) |
+				Self::Invalid |
+				Self::Dropped
+		)
+	}
+
+	/// Returns true if the transaction could be re-submitted to the pool in the future.
+	///
+	/// For example, a `Dropped` transaction may enter the pool again once other transactions
+	/// leave it. An `Invalid` transaction is not retriable, since a bad nonce or signature
+	/// never becomes valid.
+	pub fn is_retriable(&self) -> bool {
+		matches!(
+			self,
+			// The maximum number of finality watchers has been reached.
+			Self::FinalityTimeout(_) |
+				// The transaction exceeded the limits of the pool.
+				Self::Dropped
+		)
+	}
+}
+
 /// The stream of transaction events.
 pub type TransactionStatusStream<Hash, BlockHash> =
 	dyn Stream<Item = TransactionStatus<Hash, BlockHash>> + Send;
@@ -519,4 +548,25 @@ mod tests {
 		let event_dec: TransactionStatus<u8, u8> = serde_json::from_str(exp).unwrap();
 		assert_eq!(event_dec, TransactionStatus::Finalized((1, 0)));
 	}
+
+	#[test]
+	fn tx_status_final_and_retriable() {
+		let events: [(TransactionStatus<u8, u8>, bool, bool); 10] = [
+			(TransactionStatus::Future, false, false),
+			(TransactionStatus::Ready, false, false),
+			(TransactionStatus::Broadcast(vec![]), false, false),
+			(TransactionStatus::InBlock((1, 0)), false, false),
+			(TransactionStatus::Retracted(1), false, false),
+			(TransactionStatus::FinalityTimeout(1), true, true),
+			(TransactionStatus::Finalized((1, 0)), true, false),
+			(TransactionStatus::Usurped(1), true, false),
+			(TransactionStatus::Dropped, true, true),
+			(TransactionStatus::Invalid, true, false),
+		];
+
+		for (event, is_final, is_retriable) in events {
+			assert_eq!(event.is_final(), is_final, "{:?}", event);
+			assert_eq!(event.is_retriable(), is_retriable, "{:?}", event);
+		}
+	}
 }
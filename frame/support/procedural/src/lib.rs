@@ -29,6 +29,7 @@ mod match_and_insert;
 mod no_bound;
 mod pallet;
 mod pallet_error;
+mod serial_mock_test;
 mod storage_alias;
 mod transactional;
 mod tt_macro;
@@ -412,6 +413,13 @@ pub fn require_transactional(attr: TokenStream, input: TokenStream) -> TokenStre
 		.unwrap_or_else(|e| e.to_compile_error().into())
 }
 
+/// Declare a test holding the `serial_lock` of a group and running in externalities. Docs are at
+/// `frame_system::mocking::serial_mock_test`.
+#[proc_macro_attribute]
+pub fn serial_mock_test(attr: TokenStream, input: TokenStream) -> TokenStream {
+	serial_mock_test::serial_mock_test(attr, input).unwrap_or_else(|e| e.to_compile_error().into())
+}
+
 /// Derive [`Clone`] but do not bound any generic. Docs are at `frame_support::CloneNoBound`.
 #[proc_macro_derive(CloneNoBound)]
 pub fn derive_clone_no_bound(input: TokenStream) -> TokenStream {
@@ -0,0 +1,66 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use frame_support_procedural_tools::generate_crate_access_2018;
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{
+	parse::{Parse, ParseStream},
+	parse_quote, Expr, Ident, ItemFn, Result, Token,
+};
+
+mod keyword {
+	syn::custom_keyword!(ext);
+}
+
+/// The arguments of the attribute: `group` and optionally `ext = <expr>`.
+struct SerialMockTestArgs {
+	group: Ident,
+	ext: Option<Expr>,
+}
+
+impl Parse for SerialMockTestArgs {
+	fn parse(input: ParseStream) -> Result<Self> {
+		let group = input.parse()?;
+		let mut ext = None;
+		if input.parse::<Option<Token![,]>>()?.is_some() && !input.is_empty() {
+			input.parse::<keyword::ext>()?;
+			input.parse::<Token![=]>()?;
+			ext = Some(input.parse()?);
+			input.parse::<Option<Token![,]>>()?;
+		}
+		Ok(Self { group, ext })
+	}
+}
+
+pub fn serial_mock_test(attr: TokenStream, input: TokenStream) -> Result<TokenStream> {
+	let SerialMockTestArgs { group, ext } = syn::parse(attr)?;
+	let ItemFn { attrs, vis, sig, block } = syn::parse(input)?;
+
+	let frame_system = generate_crate_access_2018("frame-system")?;
+	let ext = ext.unwrap_or_else(|| parse_quote!(new_test_ext()));
+	let output = quote! {
+		#[test]
+		#(#attrs)*
+		#vis #sig {
+			#frame_system::serial_guard!(#group);
+			#ext.execute_with(|| #block)
+		}
+	};
+
+	Ok(output.into())
+}
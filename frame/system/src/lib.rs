@@ -113,7 +113,6 @@ pub(crate) mod mock;
 pub mod offchain;
 
 mod extensions;
-#[cfg(feature = "std")]
 pub mod mocking;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,222 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Deterministic adversarial inputs for the vulnerability classes pallet reviews keep finding:
+//! overflows near the maximum of a type, divisions by zero on empty sets, duplicates in lists
+//! that should be unique and deeply nested calls.
+//!
+//! Every generator returns the inputs together with a description, meant to be run with
+//! [`for_each_adversarial_input!`](crate::for_each_adversarial_input).
+
+use scale_info::prelude::{format, string::String};
+use sp_runtime::traits::{AtLeast32BitUnsigned, One, Zero};
+use sp_std::{vec, vec::Vec};
+
+/// The values of `B` at which arithmetic is most likely to overflow.
+pub fn max_balances<B: AtLeast32BitUnsigned + Copy>() -> Vec<(String, B)> {
+	let max = B::max_value();
+	let half = max / (B::one() + B::one());
+	vec![
+		("MAX".into(), max),
+		("MAX - 1".into(), max - B::one()),
+		("MAX / 2".into(), half),
+		("MAX / 2 + 1".into(), half + B::one()),
+	]
+}
+
+/// The values at which divisions and "empty set" logic are most likely to fail.
+pub fn zero_and_one_values<N: Zero + One>() -> Vec<(String, N)> {
+	vec![("zero".into(), N::zero()), ("one".into(), N::one())]
+}
+
+/// Vectors of `len` items built from `template`, all containing duplicates.
+///
+/// # Panics
+///
+/// If `template` is empty.
+pub fn duplicate_heavy_vec<I: Clone>(template: &[I], len: usize) -> Vec<(String, Vec<I>)> {
+	assert!(!template.is_empty(), "a template is needed to build the vectors");
+	let cycled = || template.iter().cloned().cycle();
+	let mut last_duplicated = cycled().take(len.saturating_sub(1)).collect::<Vec<_>>();
+	last_duplicated.push(template[0].clone());
+	vec![
+		(format!("the first item {} times", len), vec![template[0].clone(); len]),
+		(format!("the template repeated to {} items", len), cycled().take(len).collect()),
+		(
+			format!("each item twice in a row, {} items", len),
+			template
+				.iter()
+				.flat_map(|item| [item.clone(), item.clone()])
+				.cycle()
+				.take(len)
+				.collect(),
+		),
+		(format!("the first item again at the end of {} items", len), last_duplicated),
+	]
+}
+
+/// `call` wrapped by `wrap` around `depth` times, to test the nesting limit of wrapper calls
+/// like `batch` or `as_derivative`.
+///
+/// The inputs are nested `depth - 1`, `depth` and `depth + 1` times.
+pub fn nested_depth_bomb<Call: Clone>(
+	call: Call,
+	wrap: impl Fn(Call) -> Call,
+	depth: u32,
+) -> Vec<(String, Call)> {
+	let nest = |times: u32| (0..times).fold(call.clone(), |call, _| wrap(call));
+	[depth.saturating_sub(1), depth, depth.saturating_add(1)]
+		.into_iter()
+		.map(|times| (format!("nested {} times", times), nest(times)))
+		.collect()
+}
+
+/// Run `body` and add `description` to the message if it panics.
+#[cfg(feature = "std")]
+pub fn run_described<R>(description: &str, body: impl FnOnce() -> R) -> R {
+	std::panic::catch_unwind(std::panic::AssertUnwindSafe(body)).unwrap_or_else(|payload| {
+		let message = payload
+			.downcast_ref::<&str>()
+			.map(|message| message.to_string())
+			.or_else(|| payload.downcast_ref::<String>().cloned())
+			.unwrap_or_else(|| "non-string panic payload".into());
+		panic!("failed for adversarial input `{}`: {}", description, message)
+	})
+}
+
+/// Run the body for every input of a generator of [`mocking::adversarial`](self), naming the
+/// input in the failure message.
+///
+/// ```ignore
+/// for_each_adversarial_input!(max_balances::<u128>(), |balance| {
+///     assert_ok!(Pallet::<Test>::deposit(RuntimeOrigin::signed(1), balance));
+/// });
+/// ```
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! for_each_adversarial_input {
+	( $inputs:expr, |$input:pat_param| $body:expr $(,)? ) => {
+		for (description, $input) in $inputs {
+			$crate::mocking::adversarial::run_described(&description, || $body);
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn max_balances_are_near_the_maximum() {
+		let values = max_balances::<u64>();
+		assert_eq!(
+			values,
+			vec![
+				("MAX".to_string(), u64::MAX),
+				("MAX - 1".to_string(), u64::MAX - 1),
+				("MAX / 2".to_string(), u64::MAX / 2),
+				("MAX / 2 + 1".to_string(), u64::MAX / 2 + 1),
+			]
+		);
+	}
+
+	#[test]
+	fn zero_and_one_values_are_zero_and_one() {
+		assert_eq!(
+			zero_and_one_values::<u128>(),
+			vec![("zero".to_string(), 0), ("one".to_string(), 1)]
+		);
+	}
+
+	#[test]
+	fn duplicate_heavy_vecs_contain_duplicates() {
+		let vecs = duplicate_heavy_vec(&[1, 2, 3], 5);
+		let values = vecs.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>();
+		assert_eq!(
+			values,
+			vec![
+				vec![1, 1, 1, 1, 1],
+				vec![1, 2, 3, 1, 2],
+				vec![1, 1, 2, 2, 3],
+				vec![1, 2, 3, 1, 1]
+			]
+		);
+		for (_, vec) in vecs {
+			let mut unique = vec.clone();
+			unique.sort();
+			unique.dedup();
+			assert!(unique.len() < vec.len());
+		}
+	}
+
+	#[test]
+	fn nested_depth_bombs_nest_around_the_depth() {
+		#[derive(Clone, PartialEq, Debug)]
+		enum Call {
+			Leaf,
+			Batch(Vec<Call>),
+		}
+		let depth = |mut call: &Call| {
+			let mut depth = 0;
+			while let Call::Batch(calls) = call {
+				call = &calls[0];
+				depth += 1;
+			}
+			depth
+		};
+
+		let bombs = nested_depth_bomb(Call::Leaf, |call| Call::Batch(vec![call]), 6);
+		assert_eq!(bombs.iter().map(|(_, call)| depth(call)).collect::<Vec<_>>(), vec![5, 6, 7]);
+		assert_eq!(bombs[1].0, "nested 6 times");
+	}
+
+	/// A toy pallet summing balances without handling overflow.
+	///
+	/// Relies on `overflow-checks`, which are enabled for the `test` profile of the workspace.
+	fn unchecked_total(balances: &[u64]) -> u64 {
+		balances.iter().sum()
+	}
+
+	fn checked_total(balances: &[u64]) -> Option<u64> {
+		balances.iter().try_fold(0u64, |total, balance| total.checked_add(*balance))
+	}
+
+	#[test]
+	fn checked_arithmetic_passes_adversarial_inputs() {
+		crate::for_each_adversarial_input!(max_balances::<u64>(), |balance| {
+			assert_eq!(checked_total(&[balance]), Some(balance));
+			assert!(checked_total(&[balance, balance, balance]).is_none());
+		});
+	}
+
+	#[test]
+	#[cfg_attr(not(debug_assertions), ignore = "needs `overflow-checks`")]
+	#[should_panic(expected = "failed for adversarial input `MAX`: attempt to add with overflow")]
+	fn unchecked_sum_is_caught_at_max_balances() {
+		crate::for_each_adversarial_input!(max_balances::<u64>(), |balance| {
+			unchecked_total(&[balance, 1]);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "failed for adversarial input `zero`: attempt to divide by zero")]
+	fn division_by_empty_set_is_caught() {
+		crate::for_each_adversarial_input!(zero_and_one_values::<u64>(), |members| {
+			let _share = 100 / members;
+		});
+	}
+}
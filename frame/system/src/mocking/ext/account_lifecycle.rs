@@ -0,0 +1,236 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Record the accounts created and reaped by the System pallet.
+
+use crate::{pallet_prelude::BlockNumberFor, Config};
+use codec::{Decode, Encode};
+use frame_support::{
+	storage::unhashed,
+	traits::{OnKilledAccount, OnNewAccount},
+};
+use std::marker::PhantomData;
+
+/// The storage key of the log of [`MockAccountLifecycle`].
+const ACCOUNT_LIFECYCLE_KEY: &[u8] = b":mock_account_lifecycle:";
+
+/// What happened to an account, see [`MockAccountLifecycle`].
+#[derive(Encode, Decode, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AccountLifecycleEvent {
+	/// The account was created.
+	Created,
+	/// The account was reaped.
+	Reaped,
+}
+
+/// An entry of the log of [`MockAccountLifecycle`].
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct AccountLifecycleEntry<AccountId, BlockNumber> {
+	/// What happened to the account.
+	pub event: AccountLifecycleEvent,
+	/// The account.
+	pub who: AccountId,
+	/// The block number at the time.
+	pub block: BlockNumber,
+	/// The index of the extrinsic being applied at the time, if any.
+	pub extrinsic_index: Option<u32>,
+}
+
+/// Records the account lifecycle in the externalities, to be used as the `OnNewAccount` and
+/// `OnKilledAccount` of a mock runtime.
+///
+/// The calls are forwarded to `Inner`. The log is read with [`lifecycle_log`],
+/// [`assert_account_created`] and [`assert_account_reaped`].
+pub struct MockAccountLifecycle<T, Inner = ()>(PhantomData<(T, Inner)>);
+
+impl<T: Config, Inner> MockAccountLifecycle<T, Inner> {
+	fn record(event: AccountLifecycleEvent, who: &T::AccountId) {
+		let entry = AccountLifecycleEntry {
+			event,
+			who: who.clone(),
+			block: crate::Pallet::<T>::block_number(),
+			extrinsic_index: crate::Pallet::<T>::extrinsic_index(),
+		};
+		sp_io::storage::append(ACCOUNT_LIFECYCLE_KEY, entry.encode());
+	}
+}
+
+impl<T: Config, Inner: OnNewAccount<T::AccountId>> OnNewAccount<T::AccountId>
+	for MockAccountLifecycle<T, Inner>
+{
+	fn on_new_account(who: &T::AccountId) {
+		Self::record(AccountLifecycleEvent::Created, who);
+		Inner::on_new_account(who);
+	}
+}
+
+impl<T: Config, Inner: OnKilledAccount<T::AccountId>> OnKilledAccount<T::AccountId>
+	for MockAccountLifecycle<T, Inner>
+{
+	fn on_killed_account(who: &T::AccountId) {
+		Self::record(AccountLifecycleEvent::Reaped, who);
+		Inner::on_killed_account(who);
+	}
+}
+
+/// The account lifecycle recorded by [`MockAccountLifecycle`], oldest first.
+pub fn lifecycle_log<T: Config>() -> Vec<AccountLifecycleEntry<T::AccountId, BlockNumberFor<T>>> {
+	unhashed::get_or_default(ACCOUNT_LIFECYCLE_KEY)
+}
+
+fn assert_lifecycle_event<T: Config>(event: AccountLifecycleEvent, who: &T::AccountId) {
+	let log = lifecycle_log::<T>();
+	assert!(
+		log.iter().any(|entry| entry.event == event && &entry.who == who),
+		"no `{:?}` event for {:?} in the account lifecycle: {:?}",
+		event,
+		who,
+		log,
+	);
+}
+
+/// Assert that [`MockAccountLifecycle`] recorded the creation of `who`.
+#[track_caller]
+pub fn assert_account_created<T: Config>(who: &T::AccountId) {
+	assert_lifecycle_event::<T>(AccountLifecycleEvent::Created, who)
+}
+
+/// Assert that [`MockAccountLifecycle`] recorded the reaping of `who`.
+#[track_caller]
+pub fn assert_account_reaped<T: Config>(who: &T::AccountId) {
+	assert_lifecycle_event::<T>(AccountLifecycleEvent::Reaped, who)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mocking::{AccountRefDeltas, MockDispatcher};
+	use frame_support::dispatch::{DispatchInfo, DispatchResultWithPostInfo, PostDispatchInfo};
+	use sp_runtime::traits::Dispatchable;
+
+	mod lifecycle {
+		use super::*;
+		use crate as frame_system;
+		use frame_support::derive_impl;
+
+		type Block = frame_system::mocking::MockBlock<Test>;
+
+		frame_support::construct_runtime!(
+			pub enum Test
+			{
+				System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+			}
+		);
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Test {
+			type Block = Block;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = PalletInfo;
+			type OnNewAccount = MockAccountLifecycle<Test>;
+			type OnKilledAccount = MockAccountLifecycle<Test, crate::mock::RecordKilled>;
+		}
+
+		pub fn new_test_ext() -> sp_io::TestExternalities {
+			use sp_runtime::BuildStorage;
+			crate::GenesisConfig::<Test>::default().build_storage().unwrap().into()
+		}
+
+		/// A toy dispatchable moving the provider of an account to another one.
+		pub struct Call {
+			pub from: u64,
+			pub to: u64,
+		}
+
+		impl Dispatchable for Call {
+			type RuntimeOrigin = RuntimeOrigin;
+			type Config = Test;
+			type Info = DispatchInfo;
+			type PostInfo = PostDispatchInfo;
+
+			fn dispatch(self, _: RuntimeOrigin) -> DispatchResultWithPostInfo {
+				System::inc_providers(&self.to);
+				System::dec_providers(&self.from)?;
+				Ok(().into())
+			}
+		}
+	}
+
+	#[test]
+	fn account_lifecycle_and_ref_deltas_are_reported() {
+		use lifecycle::{new_test_ext, Call, RuntimeOrigin, System, Test};
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(2);
+			System::inc_providers(&1);
+			assert_account_created::<Test>(&1);
+
+			System::set_extrinsic_index(4);
+			let (result, deltas) = MockDispatcher::<Test>::default()
+				.dispatch_with_account_deltas(Call { from: 1, to: 2 }, RuntimeOrigin::root());
+			assert!(result.is_ok());
+			assert_eq!(
+				deltas,
+				[
+					(1, AccountRefDeltas { providers: -1, ..Default::default() }),
+					(2, AccountRefDeltas { providers: 1, ..Default::default() }),
+				]
+				.into()
+			);
+
+			assert_account_reaped::<Test>(&1);
+			assert_eq!(
+				lifecycle_log::<Test>(),
+				vec![
+					AccountLifecycleEntry {
+						event: AccountLifecycleEvent::Created,
+						who: 1,
+						block: 2,
+						extrinsic_index: Some(0),
+					},
+					AccountLifecycleEntry {
+						event: AccountLifecycleEvent::Created,
+						who: 2,
+						block: 2,
+						extrinsic_index: Some(4),
+					},
+					AccountLifecycleEntry {
+						event: AccountLifecycleEvent::Reaped,
+						who: 1,
+						block: 2,
+						extrinsic_index: Some(4),
+					},
+				]
+			);
+			// The lifecycle is forwarded to the inner handler.
+			assert_eq!(crate::mock::Killed::get(), vec![1]);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "no `Reaped` event for 2 in the account lifecycle")]
+	fn missing_lifecycle_event_is_reported() {
+		use lifecycle::{new_test_ext, System, Test};
+
+		new_test_ext().execute_with(|| {
+			System::inc_providers(&2);
+			assert_account_reaped::<Test>(&2);
+		});
+	}
+}
@@ -0,0 +1,278 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Build and execute mock blocks.
+
+use super::check_invariants;
+use crate::{pallet_prelude::BlockNumberFor, Config};
+use codec::Encode;
+use frame_support::{
+	dispatch::{DispatchClass, DispatchResultWithPostInfo, GetDispatchInfo},
+	traits::{Get, OnFinalize, OnInitialize},
+};
+use sp_runtime::{
+	traits::{Block as BlockT, Header, One},
+	Digest, DigestItem,
+};
+use std::marker::PhantomData;
+
+/// The extrinsics of the blocks of the runtime `T`.
+pub type ExtrinsicFor<T> = <<T as Config>::Block as BlockT>::Extrinsic;
+
+/// Dispatches an extrinsic of the runtime `T`, see [`MockBlockBuilder::with_extrinsics`].
+type DispatchFor<T> = Box<dyn Fn(ExtrinsicFor<T>) -> DispatchResultWithPostInfo>;
+
+/// Builds and executes blocks on top of the current block, initializing and finalizing each of
+/// them with the System pallet like the `Executive` would.
+///
+/// Only the hooks of the pallets declared with [`Self::with_hooks_of`] are executed. The
+/// extrinsics are dispatched by the closure given to [`Self::with_extrinsics`], without the
+/// signed extensions, and noted by the System pallet with their result, so they are part of the
+/// extrinsics root and counted, and an `ExtrinsicSuccess` or `ExtrinsicFailed` event is deposited
+/// with their actual weight.
+///
+/// ```
+/// # use frame_support::derive_impl;
+/// # use sp_runtime::{traits::{Dispatchable, Header}, BuildStorage, DigestItem};
+/// # type Block = frame_system::mocking::MockBlock<Test>;
+/// # frame_support::construct_runtime!(
+/// #     pub enum Test
+/// #     {
+/// #         System: frame_system,
+/// #     }
+/// # );
+/// # #[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+/// # impl frame_system::Config for Test {
+/// #     type Block = Block;
+/// #     type RuntimeOrigin = RuntimeOrigin;
+/// #     type RuntimeCall = RuntimeCall;
+/// #     type RuntimeEvent = RuntimeEvent;
+/// #     type PalletInfo = PalletInfo;
+/// # }
+/// # fn main() {
+/// # let mut ext: sp_io::TestExternalities =
+/// #     RuntimeGenesisConfig::default().build_storage().unwrap().into();
+/// # ext.execute_with(|| {
+/// use frame_system::mocking::{MockBlockBuilder, MockUncheckedExtrinsic};
+///
+/// let remark = RuntimeCall::System(frame_system::Call::remark_with_event { remark: vec![1] });
+/// let blocks = MockBlockBuilder::<Test>::new()
+///     .with_hooks_of::<AllPalletsWithoutSystem>()
+///     .with_digest_item(DigestItem::Other(b"mock".to_vec()))
+///     .with_extrinsics(vec![MockUncheckedExtrinsic::<Test>::new_unsigned(remark)], |xt| {
+///         xt.function.dispatch(RuntimeOrigin::signed(1))
+///     })
+///     .build_and_execute(5);
+/// assert_eq!(System::block_hash(5), blocks[4].header.hash());
+/// assert_eq!(System::events().len(), 2);
+/// # });
+/// # }
+/// ```
+pub struct MockBlockBuilder<T: Config, Hooks = ()> {
+	digest: Digest,
+	extrinsics: Vec<ExtrinsicFor<T>>,
+	dispatch: Option<DispatchFor<T>>,
+	_phantom: PhantomData<Hooks>,
+}
+
+impl<T: Config> Default for MockBlockBuilder<T> {
+	fn default() -> Self {
+		Self {
+			digest: Default::default(),
+			extrinsics: Vec::new(),
+			dispatch: None,
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: Config> MockBlockBuilder<T> {
+	/// Create a builder of empty blocks without hooks.
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl<T, Hooks> MockBlockBuilder<T, Hooks>
+where
+	T: Config,
+	Hooks: OnInitialize<BlockNumberFor<T>> + OnFinalize<BlockNumberFor<T>>,
+	ExtrinsicFor<T>: GetDispatchInfo,
+{
+	/// Execute the hooks of the pallets `H`, a tuple like `(Staking, Session)` or
+	/// `AllPalletsWithoutSystem`, in every block.
+	pub fn with_hooks_of<H>(self) -> MockBlockBuilder<T, H> {
+		MockBlockBuilder {
+			digest: self.digest,
+			extrinsics: self.extrinsics,
+			dispatch: self.dispatch,
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Add `item` to the digest of every block.
+	pub fn with_digest_item(mut self, item: DigestItem) -> Self {
+		self.digest.push(item);
+		self
+	}
+
+	/// Include `extrinsics` in every block, each dispatched by `dispatch`, e.g. with the origin of
+	/// its signer.
+	pub fn with_extrinsics(
+		mut self,
+		extrinsics: Vec<ExtrinsicFor<T>>,
+		dispatch: impl Fn(ExtrinsicFor<T>) -> DispatchResultWithPostInfo + 'static,
+	) -> Self {
+		self.extrinsics = extrinsics;
+		self.dispatch = Some(Box::new(dispatch));
+		self
+	}
+
+	/// Execute `n_blocks` blocks on top of the current one and return them.
+	///
+	/// The parent of the first block is the hash of the current block in
+	/// [`BlockHash`](crate::BlockHash). The hash of every block is recorded in `BlockHash` once
+	/// it is finalized, so the last `BlockHashCount` blocks can be queried with
+	/// [`block_hash`](crate::Pallet::block_hash) afterwards.
+	pub fn build_and_execute(&self, n_blocks: u32) -> Vec<T::Block> {
+		let mut number = crate::Pallet::<T>::block_number();
+		let mut parent_hash = crate::Pallet::<T>::block_hash(number);
+		let mut blocks = Vec::with_capacity(n_blocks as usize);
+		for _ in 0..n_blocks {
+			number += One::one();
+			crate::Pallet::<T>::reset_events();
+			crate::Pallet::<T>::initialize(&number, &parent_hash, &self.digest);
+			let weight =
+				Hooks::on_initialize(number).saturating_add(T::BlockWeights::get().base_block);
+			crate::Pallet::<T>::register_extra_weight_unchecked(weight, DispatchClass::Mandatory);
+			crate::Pallet::<T>::note_finished_initialize();
+
+			if let Some(dispatch) = &self.dispatch {
+				for extrinsic in &self.extrinsics {
+					let info = extrinsic.get_dispatch_info();
+					crate::Pallet::<T>::note_extrinsic(extrinsic.encode());
+					let result = dispatch(extrinsic.clone());
+					crate::Pallet::<T>::note_applied_extrinsic(&result, info);
+				}
+			}
+			crate::Pallet::<T>::note_finished_extrinsics();
+
+			Hooks::on_finalize(number);
+			let header = crate::Pallet::<T>::finalize();
+			parent_hash = header.hash();
+			crate::BlockHash::<T>::insert(number, parent_hash);
+			check_invariants(&format!("after block {:?}", number));
+			blocks.push(T::Block::new(header, self.extrinsics.clone()));
+		}
+		blocks
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System, Test};
+
+	#[test]
+	fn block_builder_records_the_last_block_hashes() {
+		use sp_runtime::{traits::Header as _, DigestItem};
+
+		new_test_ext().execute_with(|| {
+			let genesis = System::block_hash(0);
+			let item = DigestItem::Other(b"mock".to_vec());
+			let blocks = MockBlockBuilder::<Test>::new()
+				.with_digest_item(item.clone())
+				.build_and_execute(15);
+			assert_eq!(blocks.len(), 15);
+			assert_eq!(System::block_number(), 15);
+			assert_eq!(*blocks[0].header.parent_hash(), genesis);
+			for (i, block) in blocks.iter().enumerate() {
+				assert_eq!(*block.header.number(), i as u64 + 1);
+				assert_eq!(block.header.digest().logs, vec![item.clone()]);
+				if i > 0 {
+					assert_eq!(*block.header.parent_hash(), blocks[i - 1].header.hash());
+				}
+			}
+
+			// Only the last `BlockHashCount` blocks and the current one are kept.
+			for n in 5..=15 {
+				assert_eq!(System::block_hash(n), blocks[n as usize - 1].header.hash());
+			}
+			assert_eq!(System::block_hash(4), Default::default());
+
+			// The next blocks are built on top of the last one.
+			let next = MockBlockBuilder::<Test>::new().build_and_execute(1);
+			assert_eq!(*next[0].header.parent_hash(), blocks[14].header.hash());
+			assert!(next[0].header.digest().logs.is_empty());
+		});
+	}
+
+	#[test]
+	fn block_builder_runs_the_hooks_and_dispatches_the_extrinsics() {
+		use super::super::time_travel::tests::time_travel::*;
+		use crate::mock::{RuntimeCall, RuntimeEvent, RuntimeOrigin};
+		use sp_runtime::traits::{BlakeTwo256, Dispatchable, Header as _};
+
+		new_test_ext().execute_with(|| {
+			let extrinsics = [
+				crate::Call::remark_with_event { remark: vec![1] },
+				// Fails, as it needs the root origin.
+				crate::Call::set_heap_pages { pages: 1 },
+			]
+			.into_iter()
+			.map(|call| {
+				crate::mocking::MockUncheckedExtrinsic::<Test>::new_unsigned(RuntimeCall::System(
+					call,
+				))
+			})
+			.collect::<Vec<_>>();
+			let blocks = MockBlockBuilder::<Test>::new()
+				.with_hooks_of::<(Session,)>()
+				.with_extrinsics(extrinsics.clone(), |xt| {
+					xt.function.dispatch(RuntimeOrigin::signed(1))
+				})
+				.build_and_execute(3);
+			assert_eq!(session_blocks(), 3);
+
+			let root = crate::extrinsics_data_root::<BlakeTwo256>(
+				extrinsics.iter().map(Encode::encode).collect(),
+			);
+			for block in &blocks {
+				assert_eq!(block.extrinsics, extrinsics);
+				assert_eq!(*block.header.extrinsics_root(), root);
+			}
+			let events =
+				System::events().into_iter().map(|record| record.event).collect::<Vec<_>>();
+			assert_eq!(events.len(), 3);
+			assert!(matches!(
+				events[0],
+				RuntimeEvent::System(crate::Event::Remarked { sender: 1, .. })
+			));
+			assert!(matches!(
+				events[1],
+				RuntimeEvent::System(crate::Event::ExtrinsicSuccess { .. })
+			));
+			assert!(matches!(
+				events[2],
+				RuntimeEvent::System(crate::Event::ExtrinsicFailed {
+					dispatch_error: sp_runtime::DispatchError::BadOrigin,
+					..
+				})
+			));
+		});
+	}
+}
@@ -0,0 +1,283 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Audit the stored entries that no longer fit a shrunk bound.
+
+use codec::{Decode, DecodeAll, Encode};
+use frame_support::{
+	storage::{storage_prefix, unhashed},
+	traits::PalletInfoAccess,
+};
+use sp_core::hexdisplay::HexDisplay;
+use std::marker::PhantomData;
+
+/// What [`BoundShrinkAudit::truncate_oversized`] does with the entries exceeding the new bound.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ShrinkPolicy {
+	/// Remove the entry.
+	Drop,
+	/// Keep the first items of the entry, up to the new bound.
+	KeepFirst,
+	/// Leave the storage untouched and fail.
+	Fail,
+}
+
+/// An entry altered by [`BoundShrinkAudit::truncate_oversized`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ShrinkChange {
+	/// The entry of `len` items was removed.
+	Dropped { key: Vec<u8>, len: u32 },
+	/// The entry was truncated from `from` to `to` items.
+	Truncated { key: Vec<u8>, from: u32, to: u32 },
+}
+
+/// The result of [`BoundShrinkAudit::audit`].
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct ShrinkReport {
+	/// The number of entries found under the prefix.
+	pub scanned: u32,
+	/// The key and length of the entries which fit the old bound but not the new one.
+	pub oversized: Vec<(Vec<u8>, u32)>,
+	/// The keys of the entries which don't decode, even with the old bound.
+	pub invalid: Vec<Vec<u8>>,
+}
+
+/// Audits the entries of a storage map of bounded collections before shrinking their bound.
+///
+/// Values are decoded as a `Vec<Item>`, which shares its encoding with `BoundedVec<Item, _>`
+/// and, with `Item = (K, V)`, with `BoundedBTreeMap<K, V, _>`. The entries longer than the new
+/// bound won't decode anymore after the upgrade, see [`Self::truncate_oversized`] to migrate them.
+pub struct BoundShrinkAudit<Item> {
+	prefix: Vec<u8>,
+	old_bound: u32,
+	new_bound: u32,
+	_phantom: PhantomData<Item>,
+}
+
+impl<Item: Encode + Decode> BoundShrinkAudit<Item> {
+	/// Audit the entries starting with `prefix`, shrinking their bound from `old_bound` to
+	/// `new_bound`.
+	pub fn new(prefix: &[u8], old_bound: u32, new_bound: u32) -> Self {
+		assert!(
+			new_bound <= old_bound,
+			"the new bound {} is above the old one {}",
+			new_bound,
+			old_bound
+		);
+		Self { prefix: prefix.to_vec(), old_bound, new_bound, _phantom: PhantomData }
+	}
+
+	/// Audit the storage map `storage` of pallet `P`.
+	pub fn for_storage<P: PalletInfoAccess>(storage: &str, old_bound: u32, new_bound: u32) -> Self {
+		Self::new(&storage_prefix(P::name().as_bytes(), storage.as_bytes()), old_bound, new_bound)
+	}
+
+	/// The entries under the prefix, decoded if they fit the old bound.
+	fn entries(&self) -> Vec<(Vec<u8>, Option<Vec<Item>>)> {
+		let mut entries = Vec::new();
+		let mut key = self.prefix.clone();
+		while let Some(next) =
+			sp_io::storage::next_key(&key).filter(|k| k.starts_with(&self.prefix))
+		{
+			let items = unhashed::get_raw(&next)
+				.and_then(|value| Vec::<Item>::decode_all(&mut &value[..]).ok())
+				.filter(|items| items.len() <= self.old_bound as usize);
+			entries.push((next.clone(), items));
+			key = next;
+		}
+		entries
+	}
+
+	/// Report the entries which would fail to decode with the new bound.
+	pub fn audit(&self) -> ShrinkReport {
+		let mut report = ShrinkReport::default();
+		for (key, items) in self.entries() {
+			report.scanned += 1;
+			match items {
+				Some(items) if items.len() > self.new_bound as usize =>
+					report.oversized.push((key, items.len() as u32)),
+				Some(_) => {},
+				None => report.invalid.push(key),
+			}
+		}
+		report
+	}
+
+	/// Migrate the entries longer than the new bound according to `policy`.
+	///
+	/// Invalid entries are left untouched. Returns the altered entries, or the oversized keys
+	/// with [`ShrinkPolicy::Fail`].
+	pub fn truncate_oversized(&self, policy: ShrinkPolicy) -> Result<Vec<ShrinkChange>, String> {
+		let oversized = self
+			.entries()
+			.into_iter()
+			.filter_map(|(key, items)| Some((key, items?)))
+			.filter(|(_, items)| items.len() > self.new_bound as usize)
+			.collect::<Vec<_>>();
+
+		if policy == ShrinkPolicy::Fail && !oversized.is_empty() {
+			return Err(format!(
+				"{} entries exceed the new bound of {}: [{}]",
+				oversized.len(),
+				self.new_bound,
+				oversized
+					.iter()
+					.map(|(key, _)| format!("0x{}", HexDisplay::from(key)))
+					.collect::<Vec<_>>()
+					.join(", "),
+			))
+		}
+
+		Ok(oversized
+			.into_iter()
+			.map(|(key, mut items)| {
+				let len = items.len() as u32;
+				if policy == ShrinkPolicy::Drop {
+					unhashed::kill(&key);
+					ShrinkChange::Dropped { key, len }
+				} else {
+					items.truncate(self.new_bound as usize);
+					unhashed::put_raw(&key, &items.encode());
+					ShrinkChange::Truncated { key, from: len, to: self.new_bound }
+				}
+			})
+			.collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System};
+	use std::collections::{BTreeMap, BTreeSet};
+
+	mod bound_shrink {
+		use super::*;
+		use frame_support::{traits::ConstU32, BoundedBTreeMap, BoundedVec, Twox64Concat};
+
+		#[frame_support::storage_alias]
+		pub type Lists = StorageMap<System, Twox64Concat, u32, BoundedVec<u32, ConstU32<4>>>;
+
+		#[frame_support::storage_alias]
+		pub type Maps =
+			StorageMap<System, Twox64Concat, u32, BoundedBTreeMap<u32, u32, ConstU32<4>>>;
+
+		/// `Lists` after the upgrade.
+		pub mod shrunk {
+			use super::*;
+
+			#[frame_support::storage_alias]
+			pub type Lists = StorageMap<System, Twox64Concat, u32, BoundedVec<u32, ConstU32<2>>>;
+		}
+
+		pub fn seed_lists() {
+			for len in 0..=4 {
+				Lists::insert(len, BoundedVec::truncate_from((0..len).collect()));
+			}
+		}
+
+		pub fn list_key(len: u32) -> Vec<u8> {
+			Lists::hashed_key_for(len)
+		}
+
+		pub fn audit() -> BoundShrinkAudit<u32> {
+			BoundShrinkAudit::for_storage::<System>("Lists", 4, 2)
+		}
+	}
+
+	#[test]
+	fn shrinking_a_bound_breaks_oversized_entries() {
+		use bound_shrink::*;
+
+		new_test_ext().execute_with(|| {
+			seed_lists();
+			assert_eq!(shrunk::Lists::try_get(2).map(|list| list.into_inner()), Ok(vec![0, 1]));
+			assert_eq!(shrunk::Lists::try_get(3), Err(()));
+			assert_eq!(shrunk::Lists::try_get(4), Err(()));
+		});
+	}
+
+	#[test]
+	fn bound_shrink_audit_counts_oversized_entries() {
+		use bound_shrink::*;
+
+		new_test_ext().execute_with(|| {
+			seed_lists();
+			unhashed::put_raw(&list_key(5), &(0..5u32).collect::<Vec<_>>().encode());
+			unhashed::put_raw(&list_key(6), b"garbage");
+
+			// Entries are scanned in key order.
+			let report = audit().audit();
+			assert_eq!(report.scanned, 7);
+			let mut oversized = vec![(list_key(3), 3), (list_key(4), 4)];
+			oversized.sort();
+			assert_eq!(report.oversized, oversized);
+			let mut invalid = vec![list_key(5), list_key(6)];
+			invalid.sort();
+			assert_eq!(report.invalid, invalid);
+		});
+	}
+
+	#[test]
+	fn bound_shrink_policies_migrate_oversized_entries() {
+		use bound_shrink::*;
+
+		new_test_ext().execute_with(|| {
+			seed_lists();
+			let error = audit().truncate_oversized(ShrinkPolicy::Fail).unwrap_err();
+			assert!(error.starts_with("2 entries exceed the new bound of 2: [0x"), "{}", error);
+			assert_eq!(Lists::iter().count(), 5);
+
+			let changes = audit().truncate_oversized(ShrinkPolicy::KeepFirst).unwrap();
+			assert_eq!(changes.len(), 2);
+			assert!(changes.contains(&ShrinkChange::Truncated {
+				key: list_key(4),
+				from: 4,
+				to: 2
+			}));
+			assert_eq!(shrunk::Lists::try_get(4).map(|list| list.into_inner()), Ok(vec![0, 1]));
+			assert_eq!(audit().audit().oversized, vec![]);
+			assert_eq!(audit().truncate_oversized(ShrinkPolicy::Fail), Ok(vec![]));
+		});
+
+		new_test_ext().execute_with(|| {
+			seed_lists();
+			let changes = audit().truncate_oversized(ShrinkPolicy::Drop).unwrap();
+			assert!(changes.contains(&ShrinkChange::Dropped { key: list_key(3), len: 3 }));
+			assert_eq!(shrunk::Lists::iter_keys().collect::<BTreeSet<_>>(), [0, 1, 2].into());
+		});
+	}
+
+	#[test]
+	fn bound_shrink_keeps_the_first_keys_of_maps() {
+		use bound_shrink::*;
+		use frame_support::{traits::ConstU32, BoundedBTreeMap};
+
+		new_test_ext().execute_with(|| {
+			let map = [(3, 30), (1, 10), (2, 20)].into_iter().collect::<BTreeMap<_, _>>();
+			Maps::insert(0, BoundedBTreeMap::try_from(map).unwrap());
+
+			let audit = BoundShrinkAudit::<(u32, u32)>::for_storage::<System>("Maps", 4, 2);
+			assert_eq!(audit.audit().oversized, vec![(Maps::hashed_key_for(0), 3)]);
+			audit.truncate_oversized(ShrinkPolicy::KeepFirst).unwrap();
+
+			let map =
+				unhashed::get::<BoundedBTreeMap<u32, u32, ConstU32<2>>>(&Maps::hashed_key_for(0));
+			assert_eq!(map.map(|map| map.into_inner()), Some([(1, 10), (2, 20)].into()));
+		});
+	}
+}
@@ -0,0 +1,609 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Count the reads and writes of a call, and check the weight it refunds.
+
+use crate::Config;
+use frame_support::{
+	dispatch::{GetDispatchInfo, PostDispatchInfo},
+	traits::Get,
+};
+use sp_core::storage::{ChildInfo, TrackedStorageKey};
+use sp_externalities::{Extension, ExtensionStore, Externalities, MultiRemovalResults};
+use sp_runtime::{traits::Dispatchable, Perbill, StateVersion};
+use std::{
+	any::{Any, TypeId},
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet},
+};
+
+/// The database operations counted by [`count_db_ops`].
+///
+/// Like in benchmarks, only the first access of a key is a read or write; reading a key after
+/// accessing it, or writing it again, is a repeat.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct DbOps {
+	/// The number of distinct keys read before first being written.
+	pub reads: u32,
+	/// The number of repeated reads.
+	pub repeat_reads: u32,
+	/// The number of distinct keys written.
+	pub writes: u32,
+	/// The number of repeated writes.
+	pub repeat_writes: u32,
+}
+
+/// The original value of a written key.
+struct Original {
+	child_info: Option<ChildInfo>,
+	key: Vec<u8>,
+	value: Option<Vec<u8>>,
+}
+
+#[derive(Default)]
+struct KeyTracker {
+	accessed: BTreeSet<Vec<u8>>,
+	written: BTreeSet<Vec<u8>>,
+	ops: DbOps,
+	/// The original values of the written keys, if journaled.
+	journal: Option<BTreeMap<Vec<u8>, Original>>,
+}
+
+impl KeyTracker {
+	fn read(&mut self, key: Vec<u8>) {
+		if self.accessed.insert(key) {
+			self.ops.reads += 1;
+		} else {
+			self.ops.repeat_reads += 1;
+		}
+	}
+
+	fn write(&mut self, key: Vec<u8>) {
+		if self.written.insert(key.clone()) {
+			self.accessed.insert(key);
+			self.ops.writes += 1;
+		} else {
+			self.ops.repeat_writes += 1;
+		}
+	}
+}
+
+fn child_key(child_info: &ChildInfo, key: &[u8]) -> Vec<u8> {
+	[child_info.prefixed_storage_key().as_slice(), key].concat()
+}
+
+/// Externalities counting the database operations done through them, and optionally journaling
+/// the original value of each key written.
+struct DbOpCounter<'a> {
+	inner: &'a mut dyn Externalities,
+	tracker: RefCell<KeyTracker>,
+}
+
+impl DbOpCounter<'_> {
+	fn value(&self, child_info: Option<&ChildInfo>, key: &[u8]) -> Option<Vec<u8>> {
+		match child_info {
+			Some(child_info) => self.inner.child_storage(child_info, key),
+			None => self.inner.storage(key),
+		}
+	}
+
+	/// Journal the original value of `key`, before its first write.
+	fn journal(&mut self, child_info: Option<&ChildInfo>, key: &[u8]) {
+		let tracked = child_info.map_or_else(|| key.to_vec(), |info| child_key(info, key));
+		let journaled = match &self.tracker.get_mut().journal {
+			Some(journal) => journal.contains_key(&tracked),
+			None => return,
+		};
+		if !journaled {
+			let value = self.value(child_info, key);
+			let original = Original { child_info: child_info.cloned(), key: key.to_vec(), value };
+			if let Some(journal) = &mut self.tracker.get_mut().journal {
+				journal.insert(tracked, original);
+			}
+		}
+	}
+
+	/// Journal the original values of the keys starting with `prefix`, before removing them.
+	fn journal_prefix(&mut self, child_info: Option<&ChildInfo>, prefix: &[u8]) {
+		if self.tracker.get_mut().journal.is_none() {
+			return
+		}
+		self.journal(child_info, prefix);
+		let mut key = prefix.to_vec();
+		loop {
+			let next = match child_info {
+				Some(child_info) => self.inner.next_child_storage_key(child_info, &key),
+				None => self.inner.next_storage_key(&key),
+			};
+			match next.filter(|next| next.starts_with(prefix)) {
+				Some(next) => {
+					self.journal(child_info, &next);
+					key = next;
+				},
+				None => break,
+			}
+		}
+	}
+}
+
+impl ExtensionStore for DbOpCounter<'_> {
+	fn extension_by_type_id(&mut self, type_id: TypeId) -> Option<&mut dyn Any> {
+		self.inner.extension_by_type_id(type_id)
+	}
+
+	fn register_extension_with_type_id(
+		&mut self,
+		type_id: TypeId,
+		extension: Box<dyn Extension>,
+	) -> Result<(), sp_externalities::Error> {
+		self.inner.register_extension_with_type_id(type_id, extension)
+	}
+
+	fn deregister_extension_by_type_id(
+		&mut self,
+		type_id: TypeId,
+	) -> Result<(), sp_externalities::Error> {
+		self.inner.deregister_extension_by_type_id(type_id)
+	}
+}
+
+impl Externalities for DbOpCounter<'_> {
+	fn set_offchain_storage(&mut self, key: &[u8], value: Option<&[u8]>) {
+		self.inner.set_offchain_storage(key, value)
+	}
+
+	fn storage(&self, key: &[u8]) -> Option<Vec<u8>> {
+		self.tracker.borrow_mut().read(key.to_vec());
+		self.inner.storage(key)
+	}
+
+	fn storage_hash(&self, key: &[u8]) -> Option<Vec<u8>> {
+		self.tracker.borrow_mut().read(key.to_vec());
+		self.inner.storage_hash(key)
+	}
+
+	fn child_storage_hash(&self, child_info: &ChildInfo, key: &[u8]) -> Option<Vec<u8>> {
+		self.tracker.borrow_mut().read(child_key(child_info, key));
+		self.inner.child_storage_hash(child_info, key)
+	}
+
+	fn child_storage(&self, child_info: &ChildInfo, key: &[u8]) -> Option<Vec<u8>> {
+		self.tracker.borrow_mut().read(child_key(child_info, key));
+		self.inner.child_storage(child_info, key)
+	}
+
+	fn exists_storage(&self, key: &[u8]) -> bool {
+		self.tracker.borrow_mut().read(key.to_vec());
+		self.inner.exists_storage(key)
+	}
+
+	fn exists_child_storage(&self, child_info: &ChildInfo, key: &[u8]) -> bool {
+		self.tracker.borrow_mut().read(child_key(child_info, key));
+		self.inner.exists_child_storage(child_info, key)
+	}
+
+	fn next_storage_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+		self.inner.next_storage_key(key)
+	}
+
+	fn next_child_storage_key(&self, child_info: &ChildInfo, key: &[u8]) -> Option<Vec<u8>> {
+		self.inner.next_child_storage_key(child_info, key)
+	}
+
+	fn kill_child_storage(
+		&mut self,
+		child_info: &ChildInfo,
+		maybe_limit: Option<u32>,
+		maybe_cursor: Option<&[u8]>,
+	) -> MultiRemovalResults {
+		self.journal_prefix(Some(child_info), &[]);
+		let results = self.inner.kill_child_storage(child_info, maybe_limit, maybe_cursor);
+		self.tracker.get_mut().ops.writes += results.unique;
+		results
+	}
+
+	fn clear_prefix(
+		&mut self,
+		prefix: &[u8],
+		maybe_limit: Option<u32>,
+		maybe_cursor: Option<&[u8]>,
+	) -> MultiRemovalResults {
+		self.journal_prefix(None, prefix);
+		let results = self.inner.clear_prefix(prefix, maybe_limit, maybe_cursor);
+		self.tracker.get_mut().ops.writes += results.unique;
+		results
+	}
+
+	fn clear_child_prefix(
+		&mut self,
+		child_info: &ChildInfo,
+		prefix: &[u8],
+		maybe_limit: Option<u32>,
+		maybe_cursor: Option<&[u8]>,
+	) -> MultiRemovalResults {
+		self.journal_prefix(Some(child_info), prefix);
+		let results = self.inner.clear_child_prefix(child_info, prefix, maybe_limit, maybe_cursor);
+		self.tracker.get_mut().ops.writes += results.unique;
+		results
+	}
+
+	fn place_storage(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+		self.journal(None, &key);
+		self.tracker.get_mut().write(key.clone());
+		self.inner.place_storage(key, value)
+	}
+
+	fn place_child_storage(
+		&mut self,
+		child_info: &ChildInfo,
+		key: Vec<u8>,
+		value: Option<Vec<u8>>,
+	) {
+		self.journal(Some(child_info), &key);
+		self.tracker.get_mut().write(child_key(child_info, &key));
+		self.inner.place_child_storage(child_info, key, value)
+	}
+
+	fn storage_root(&mut self, state_version: StateVersion) -> Vec<u8> {
+		self.inner.storage_root(state_version)
+	}
+
+	fn child_storage_root(
+		&mut self,
+		child_info: &ChildInfo,
+		state_version: StateVersion,
+	) -> Vec<u8> {
+		self.inner.child_storage_root(child_info, state_version)
+	}
+
+	fn storage_append(&mut self, key: Vec<u8>, value: Vec<u8>) {
+		self.journal(None, &key);
+		self.tracker.get_mut().write(key.clone());
+		self.inner.storage_append(key, value)
+	}
+
+	fn storage_start_transaction(&mut self) {
+		self.inner.storage_start_transaction()
+	}
+
+	fn storage_rollback_transaction(&mut self) -> Result<(), ()> {
+		self.inner.storage_rollback_transaction()
+	}
+
+	fn storage_commit_transaction(&mut self) -> Result<(), ()> {
+		self.inner.storage_commit_transaction()
+	}
+
+	fn storage_index_transaction(&mut self, index: u32, hash: &[u8], size: u32) {
+		self.inner.storage_index_transaction(index, hash, size)
+	}
+
+	fn storage_renew_transaction_index(&mut self, index: u32, hash: &[u8]) {
+		self.inner.storage_renew_transaction_index(index, hash)
+	}
+
+	fn wipe(&mut self) {
+		self.inner.wipe()
+	}
+
+	fn commit(&mut self) {
+		self.inner.commit()
+	}
+
+	fn read_write_count(&self) -> (u32, u32, u32, u32) {
+		let ops = self.tracker.borrow().ops;
+		(ops.reads, ops.repeat_reads, ops.writes, ops.repeat_writes)
+	}
+
+	fn reset_read_write_count(&mut self) {
+		*self.tracker.get_mut() = Default::default();
+	}
+
+	fn get_whitelist(&self) -> Vec<TrackedStorageKey> {
+		self.inner.get_whitelist()
+	}
+
+	fn set_whitelist(&mut self, new: Vec<TrackedStorageKey>) {
+		self.inner.set_whitelist(new)
+	}
+
+	fn proof_size(&self) -> Option<u32> {
+		self.inner.proof_size()
+	}
+
+	fn get_read_and_written_keys(&self) -> Vec<(Vec<u8>, u32, u32, bool)> {
+		self.inner.get_read_and_written_keys()
+	}
+}
+
+/// Execute `execute` in the current externalities, counting the database operations it does.
+///
+/// # Panics
+///
+/// If not called within externalities.
+pub fn count_db_ops<R>(execute: impl FnOnce() -> R) -> (R, DbOps) {
+	sp_externalities::with_externalities(|inner| {
+		let mut counter = DbOpCounter { inner, tracker: Default::default() };
+		let result = sp_externalities::set_and_run_with_externalities(&mut counter, execute);
+		(result, counter.tracker.into_inner().ops)
+	})
+	.expect("`count_db_ops` must be called within externalities")
+}
+
+/// Execute `execute` in the current externalities, returning the keys whose value it changed
+/// along with their new value.
+///
+/// Only the keys written by `execute` are compared, so this is proportional to the writes and not
+/// to the size of the state. Keys of child tries are prefixed by the prefixed storage key of their
+/// trie.
+///
+/// # Panics
+///
+/// If not called within externalities.
+pub(super) fn track_mutations<R>(
+	execute: impl FnOnce() -> R,
+) -> (R, BTreeMap<Vec<u8>, Option<Vec<u8>>>) {
+	sp_externalities::with_externalities(|inner| {
+		let tracker = KeyTracker { journal: Some(Default::default()), ..Default::default() };
+		let mut counter = DbOpCounter { inner, tracker: RefCell::new(tracker) };
+		let result = sp_externalities::set_and_run_with_externalities(&mut counter, execute);
+		let journal = counter.tracker.take().journal.unwrap_or_default();
+		let mutations = journal
+			.into_iter()
+			.filter_map(|(tracked, original)| {
+				let value = counter.value(original.child_info.as_ref(), &original.key);
+				(value != original.value).then_some((tracked, value))
+			})
+			.collect();
+		(result, mutations)
+	})
+	.expect("`track_mutations` must be called within externalities")
+}
+
+/// The thresholds of [`check_refund`].
+#[derive(Clone, Copy, Debug)]
+pub struct RefundCheck {
+	/// The share of the declared weight the measured database operations must reach when no
+	/// `actual_weight` is returned.
+	pub min_measured: Perbill,
+}
+
+impl Default for RefundCheck {
+	fn default() -> Self {
+		Self { min_measured: Perbill::from_percent(25) }
+	}
+}
+
+/// Dispatch `call` and check that it refunds weight if it did much less work than declared.
+///
+/// The work is measured as the weight of the database operations done by the call, using the
+/// `DbWeight` of the runtime. If this is below `check.min_measured` of the declared weight while
+/// the call returned no `actual_weight`, an error describing the numbers is returned.
+pub fn check_refund<Call>(
+	call: Call,
+	origin: Call::RuntimeOrigin,
+	check: RefundCheck,
+) -> Result<(), String>
+where
+	Call: Dispatchable<PostInfo = PostDispatchInfo> + GetDispatchInfo,
+	Call::Config: Config,
+{
+	let declared = call.get_dispatch_info().weight.ref_time();
+	let (result, ops) = count_db_ops(|| call.dispatch(origin));
+	let post_info = result.unwrap_or_else(|e| e.post_info);
+	if post_info.actual_weight.is_some() {
+		return Ok(())
+	}
+
+	let db = <Call::Config as Config>::DbWeight::get();
+	let measured = db.reads_writes(ops.reads.into(), ops.writes.into()).ref_time();
+	if measured >= check.min_measured * declared {
+		return Ok(())
+	}
+
+	let implied = |op_weight: u64| {
+		declared
+			.checked_div(op_weight)
+			.map_or_else(|| "n/a".into(), |ops| ops.to_string())
+	};
+	Err(format!(
+		"the call declared a weight of {} (up to {} reads or {} writes), but only did {} reads \
+		and {} writes, weighing {} which is below {:?} of the declared weight, and no \
+		`actual_weight` was returned to refund the difference",
+		declared,
+		implied(db.read),
+		implied(db.write),
+		ops.reads,
+		ops.writes,
+		measured,
+		check.min_measured,
+	))
+}
+
+/// Assert that dispatching `call` with `origin` refunds weight if it did much less work than
+/// declared, see [`mocking::check_refund`](crate::mocking::check_refund).
+///
+/// The thresholds can be given as a [`RefundCheck`](crate::mocking::RefundCheck), the
+/// default is used otherwise.
+#[macro_export]
+macro_rules! assert_refunds_on_cheap_path {
+	( $call:expr, $origin:expr $(,)? ) => {
+		$crate::assert_refunds_on_cheap_path!($call, $origin, ::core::default::Default::default())
+	};
+	( $call:expr, $origin:expr, $check:expr $(,)? ) => {
+		if let ::core::result::Result::Err(e) =
+			$crate::mocking::check_refund($call, $origin, $check)
+		{
+			::core::panic!("{}", e);
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, Test};
+	use codec::Encode;
+	use frame_support::{
+		dispatch::{DispatchInfo, DispatchResultWithPostInfo},
+		storage::unhashed,
+		weights::Weight,
+	};
+
+	mod refund {
+		use super::*;
+		use crate::mock::RuntimeOrigin;
+
+		pub const KEY: &[u8] = b"refund_key";
+
+		/// A toy dispatchable doing one read and two writes, unless there is nothing to do.
+		pub enum Call {
+			Unrefunded { nothing_to_do: bool },
+			Refunding { nothing_to_do: bool },
+		}
+
+		fn work(nothing_to_do: bool) -> bool {
+			if nothing_to_do {
+				return false
+			}
+			let value = unhashed::get_or_default::<u32>(KEY);
+			unhashed::put(KEY, &(value + 1));
+			unhashed::put(b"other_key", &value);
+			true
+		}
+
+		impl GetDispatchInfo for Call {
+			fn get_dispatch_info(&self) -> DispatchInfo {
+				DispatchInfo {
+					weight: crate::mock::DbWeight::get().reads_writes(1, 2),
+					..Default::default()
+				}
+			}
+		}
+
+		impl Dispatchable for Call {
+			type RuntimeOrigin = RuntimeOrigin;
+			type Config = Test;
+			type Info = DispatchInfo;
+			type PostInfo = PostDispatchInfo;
+
+			fn dispatch(self, _: RuntimeOrigin) -> DispatchResultWithPostInfo {
+				match self {
+					Call::Unrefunded { nothing_to_do } => {
+						work(nothing_to_do);
+						Ok(().into())
+					},
+					Call::Refunding { nothing_to_do } if !work(nothing_to_do) =>
+						Ok(Some(Weight::zero()).into()),
+					Call::Refunding { .. } => Ok(().into()),
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn count_db_ops_counts_distinct_keys() {
+		new_test_ext().execute_with(|| {
+			let (value, ops) = count_db_ops(|| {
+				let value = unhashed::get::<u32>(b"a");
+				unhashed::get::<u32>(b"a");
+				unhashed::put(b"b", &1u32);
+				unhashed::put(b"b", &2u32);
+				unhashed::get::<u32>(b"b");
+				value
+			});
+			assert_eq!(value, None);
+			assert_eq!(ops, DbOps { reads: 1, repeat_reads: 2, writes: 1, repeat_writes: 1 });
+			// The writes went through to the externalities.
+			assert_eq!(unhashed::get::<u32>(b"b"), Some(2));
+		});
+	}
+
+	#[test]
+	fn refund_check_accepts_full_work_and_refunds() {
+		use refund::*;
+
+		new_test_ext().execute_with(|| {
+			let origin = || crate::mock::RuntimeOrigin::root();
+			crate::assert_refunds_on_cheap_path!(
+				Call::Unrefunded { nothing_to_do: false },
+				origin()
+			);
+			crate::assert_refunds_on_cheap_path!(
+				Call::Refunding { nothing_to_do: false },
+				origin()
+			);
+			crate::assert_refunds_on_cheap_path!(Call::Refunding { nothing_to_do: true }, origin());
+			assert_eq!(unhashed::get::<u32>(KEY), Some(2));
+		});
+	}
+
+	#[test]
+	fn refund_check_flags_unrefunded_early_return() {
+		use refund::*;
+
+		new_test_ext().execute_with(|| {
+			let origin = || crate::mock::RuntimeOrigin::root();
+			assert_eq!(
+				check_refund(Call::Unrefunded { nothing_to_do: true }, origin(), Default::default()),
+				Err("the call declared a weight of 210 (up to 21 reads or 2 writes), but only did 0 \
+					reads and 0 writes, weighing 0 which is below 25% of the declared weight, \
+					and no `actual_weight` was returned to refund the difference"
+					.into())
+			);
+
+			// Without a threshold, the cheap path passes as well.
+			let check = RefundCheck { min_measured: Perbill::zero() };
+			assert!(check_refund(Call::Unrefunded { nothing_to_do: true }, origin(), check).is_ok());
+		});
+	}
+
+	#[test]
+	fn track_mutations_reports_the_changed_keys_only() {
+		new_test_ext().execute_with(|| {
+			unhashed::put(b"same", &1u32);
+			unhashed::put(b"prefix_a", &1u32);
+			unhashed::put(b"prefix_b", &1u32);
+			let child = ChildInfo::new_default(b"child");
+			sp_io::default_child_storage::set(child.storage_key(), b"key", b"value");
+
+			let ((), mutations) = track_mutations(|| {
+				unhashed::put(b"same", &2u32);
+				unhashed::put(b"same", &1u32);
+				unhashed::put(b"new", &1u32);
+				unhashed::kill(b"absent");
+				let _ = unhashed::clear_prefix(b"prefix", None, None);
+				let _ = frame_support::storage::with_storage_layer(|| {
+					unhashed::put(b"rolled_back", &1u32);
+					Err::<(), _>(sp_runtime::DispatchError::Other("rolled back"))
+				});
+				sp_io::default_child_storage::clear(child.storage_key(), b"key");
+			});
+			assert_eq!(
+				mutations,
+				[
+					(child_key(&child, b"key"), None),
+					(b"new".to_vec(), Some(1u32.encode())),
+					(b"prefix_a".to_vec(), None),
+					(b"prefix_b".to_vec(), None),
+				]
+				.into_iter()
+				.collect()
+			);
+		});
+	}
+}
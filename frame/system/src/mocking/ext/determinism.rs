@@ -0,0 +1,240 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detect the nondeterminism of a scenario by running it several times.
+
+use super::{
+	check_invariants, first_difference, storage_pairs, MockDispatcher, MockExternalitiesBuilder,
+};
+use crate::Config;
+use sp_core::{hexdisplay::HexDisplay, storage::well_known_keys};
+use sp_runtime::{traits::One, StateVersion};
+use std::{fmt, marker::PhantomData};
+
+/// Checks that a scenario is deterministic by executing it several times, see
+/// [`assert_deterministic`].
+pub struct AssertDeterministic<T> {
+	runs: u32,
+	perturb: bool,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config> AssertDeterministic<T> {
+	/// Execute the scenario `runs` times, perturbing the environment between the runs.
+	pub fn new(runs: u32) -> Self {
+		Self { runs, perturb: true, _phantom: PhantomData }
+	}
+
+	/// Whether to perturb the environment between the runs, `true` by default.
+	///
+	/// When perturbed, the keys of the hash maps created by the scenario differ between runs and
+	/// the thread yields before each run, to flush out the nondeterminism hidden in the scenario.
+	pub fn perturb(mut self, perturb: bool) -> Self {
+		self.perturb = perturb;
+		self
+	}
+
+	/// Execute `scenario` in fresh externalities built identically for each run, and assert that
+	/// the outcome is the same.
+	///
+	/// Every run starts from the default genesis of `T` at block 1, and the runs must agree on
+	/// the value returned by the scenario, the results of the dispatches done through the given
+	/// [`MockDispatcher`], the events, the storage, the roots of the child tries and the storage
+	/// root.
+	///
+	/// # Panics
+	///
+	/// With the first divergence between the first run and another one.
+	pub fn check<R: PartialEq + fmt::Debug>(&self, scenario: impl Fn(&MockDispatcher<T>) -> R) {
+		let run = |run| {
+			if self.perturb {
+				// Each `RandomState` gets different keys, shift them by a different amount each
+				// run.
+				(0..=run).for_each(|_| drop(std::collections::hash_map::RandomState::new()));
+				std::thread::yield_now();
+			}
+
+			let mut ext = MockExternalitiesBuilder::<T>::new().build();
+			let (returned, results, events) = ext.execute_with(|| {
+				crate::Pallet::<T>::set_block_number(One::one());
+				let dispatcher = MockDispatcher::<T>::default();
+				let returned = scenario(&dispatcher);
+				check_invariants(&format!("after the scenario of run {}", run));
+				let events = crate::Pallet::<T>::read_events_no_consensus()
+					.map(|record| format!("{:?}", record.event))
+					.collect::<Vec<_>>();
+				(returned, dispatcher.results(), events)
+			});
+			// Committing records the root of each child trie in the main trie.
+			ext.commit_all().expect("no transaction is open after the scenario; qed");
+			ext.execute_with(|| {
+				let (child_roots, storage) =
+					storage_pairs().into_iter().partition::<Vec<_>, _>(|(key, _)| {
+						key.starts_with(well_known_keys::CHILD_STORAGE_KEY_PREFIX)
+					});
+				let root = sp_io::storage::root(StateVersion::V1);
+				Outcome { returned, results, events, storage, child_roots, root }
+			})
+		};
+
+		let first = run(0);
+		for i in 1..self.runs {
+			let other = run(i);
+			let diverged = |observable: &str, a: &dyn fmt::Debug, b: &dyn fmt::Debug| -> ! {
+				panic!(
+					"runs 0 and {} diverged on the {}: in run 0: {:?}, in run {}: {:?}",
+					i, observable, a, i, b
+				)
+			};
+			let hex = |pair: Option<&(Vec<u8>, Vec<u8>)>| {
+				pair.map(|(k, v)| format!("0x{} => 0x{}", HexDisplay::from(k), HexDisplay::from(v)))
+			};
+			if first.returned != other.returned {
+				diverged("returned value", &first.returned, &other.returned);
+			}
+			if first.results != other.results {
+				let (a, b) = first_difference(&first.results, &other.results);
+				diverged("dispatch results", &a, &b);
+			}
+			if first.events != other.events {
+				let (a, b) = first_difference(&first.events, &other.events);
+				diverged("events", &a, &b);
+			}
+			if first.storage != other.storage {
+				let (a, b) = first_difference(&first.storage, &other.storage);
+				diverged("storage", &hex(a), &hex(b));
+			}
+			if first.child_roots != other.child_roots {
+				let (a, b) = first_difference(&first.child_roots, &other.child_roots);
+				diverged("child tries", &hex(a), &hex(b));
+			}
+			if first.root != other.root {
+				diverged(
+					"storage root",
+					&HexDisplay::from(&first.root),
+					&HexDisplay::from(&other.root),
+				);
+			}
+		}
+	}
+}
+
+/// What the runs of [`AssertDeterministic`] are compared on.
+struct Outcome<R> {
+	returned: R,
+	results: Vec<String>,
+	events: Vec<String>,
+	storage: Vec<(Vec<u8>, Vec<u8>)>,
+	child_roots: Vec<(Vec<u8>, Vec<u8>)>,
+	root: Vec<u8>,
+}
+
+/// Execute `scenario` `runs` times, each time in fresh externalities built identically and in a
+/// perturbed environment, and assert that the outcome is the same, see [`AssertDeterministic`].
+///
+/// # Panics
+///
+/// With the first divergence between the first run and another one.
+pub fn assert_deterministic<T: Config, R: PartialEq + fmt::Debug>(
+	runs: u32,
+	scenario: impl Fn(&MockDispatcher<T>) -> R,
+) {
+	AssertDeterministic::<T>::new(runs).check(scenario)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::Test;
+	use codec::Encode;
+	use frame_support::storage::unhashed;
+
+	mod determinism {
+		use super::*;
+		use crate::mock::RuntimeOrigin;
+		use std::collections::HashMap;
+
+		pub fn remark(dispatcher: &MockDispatcher<Test>, who: u64) {
+			let call = crate::Call::<Test>::remark_with_event { remark: who.encode() };
+			let _ = dispatcher
+				.dispatch(crate::mock::RuntimeCall::System(call), RuntimeOrigin::signed(who));
+		}
+
+		/// The keys of a hash map, in iteration order.
+		pub fn hash_map_order() -> Vec<u32> {
+			(0..32).map(|i| (i, ())).collect::<HashMap<_, _>>().into_keys().collect()
+		}
+	}
+
+	#[test]
+	fn deterministic_scenario_passes() {
+		use determinism::*;
+
+		assert_deterministic::<Test, _>(3, |dispatcher| {
+			let mut accounts = hash_map_order();
+			accounts.sort();
+			for who in accounts.into_iter().take(4) {
+				remark(dispatcher, who.into());
+			}
+			sp_io::default_child_storage::set(b"child", b"key", b"value");
+			dispatcher.results().len()
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "diverged on the storage")]
+	fn hash_map_ordered_iteration_is_caught() {
+		use determinism::*;
+
+		assert_deterministic::<Test, _>(3, |_| unhashed::put(b"order", &hash_map_order()));
+	}
+
+	#[test]
+	#[should_panic(expected = "diverged on the events")]
+	fn hash_map_ordered_dispatches_are_caught() {
+		use determinism::*;
+
+		assert_deterministic::<Test, _>(3, |dispatcher| {
+			for who in hash_map_order().into_iter().take(4) {
+				remark(dispatcher, who.into());
+			}
+		});
+	}
+
+	#[test]
+	fn deterministic_scenario_passes_without_perturbation() {
+		use determinism::*;
+
+		AssertDeterministic::<Test>::new(3).perturb(false).check(|dispatcher| {
+			remark(dispatcher, 1);
+			sp_io::default_child_storage::set(b"child", b"key", b"value");
+		});
+	}
+
+	#[test]
+	#[should_panic(
+		expected = "diverged on the child tries: in run 0: Some(\"0x3a6368696c645f73746f726167\
+		653a64656661756c743a6368696c64 => 0x"
+	)]
+	fn child_trie_divergence_is_caught() {
+		use determinism::*;
+
+		assert_deterministic::<Test, _>(3, |_| {
+			sp_io::default_child_storage::set(b"child", b"order", &hash_map_order().encode())
+		});
+	}
+}
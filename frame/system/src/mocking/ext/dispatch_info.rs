@@ -0,0 +1,197 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Assert the dispatch info of calls, and classify all the calls of a pallet.
+
+use frame_support::{
+	dispatch::{DispatchClass, DispatchInfo, GetDispatchInfo, Pays},
+	traits::GetCallName,
+};
+use std::{collections::BTreeMap, fmt};
+
+/// The [`DispatchInfo`] of `call`, without dispatching it.
+pub fn dispatch_info<Call: GetDispatchInfo>(call: &Call) -> DispatchInfo {
+	call.get_dispatch_info()
+}
+
+/// Assert the [`DispatchClass`](frame_support::dispatch::DispatchClass) of a call, without
+/// dispatching it.
+#[macro_export]
+macro_rules! assert_dispatch_class {
+	( $call:expr, $class:expr $(,)? ) => {
+		::core::assert_eq!(
+			$crate::mocking::dispatch_info(&$call).class,
+			$class,
+			"unexpected dispatch class of `{}`",
+			::core::stringify!($call),
+		)
+	};
+}
+
+/// Assert the [`Pays`](frame_support::dispatch::Pays) of a call, without dispatching it.
+#[macro_export]
+macro_rules! assert_pays_fee {
+	( $call:expr, $pays:expr $(,)? ) => {
+		::core::assert_eq!(
+			$crate::mocking::dispatch_info(&$call).pays_fee,
+			$pays,
+			"unexpected `pays_fee` of `{}`",
+			::core::stringify!($call),
+		)
+	};
+}
+
+/// The classification of a call, see [`classify_all_calls`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CallClass {
+	/// The name of the call.
+	pub call: &'static str,
+	/// The class and fee payment of the sample of the call, if any.
+	pub info: Option<(DispatchClass, Pays)>,
+}
+
+/// The classification of all calls of a pallet, see [`classify_all_calls`].
+///
+/// Displayed as a table meant to be snapshotted with
+/// [`assert_golden_file`](crate::mocking::assert_golden_file).
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct CallClassification(pub Vec<CallClass>);
+
+impl fmt::Display for CallClassification {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "{:<32} {:<12} pays_fee", "call", "class")?;
+		for row in &self.0 {
+			match row.info {
+				Some((class, pays)) => {
+					let class = match class {
+						DispatchClass::Normal => "Normal",
+						DispatchClass::Operational => "Operational",
+						DispatchClass::Mandatory => "Mandatory",
+					};
+					let pays = match pays {
+						Pays::Yes => "Yes",
+						Pays::No => "No",
+					};
+					writeln!(f, "{:<32} {:<12} {}", row.call, class, pays)?
+				},
+				None => writeln!(f, "{:<32} no sample", row.call)?,
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Classify every call variant of `Call`, using one sample call per variant.
+///
+/// The variants are listed in declaration order; variants without a sample are listed as such so
+/// that new calls show up in the classification.
+///
+/// # Panics
+///
+/// If several samples are given for the same variant.
+pub fn classify_all_calls<Call: GetDispatchInfo + GetCallName>(
+	samples: impl IntoIterator<Item = Call>,
+) -> CallClassification {
+	let mut infos = BTreeMap::new();
+	for sample in samples {
+		let info = sample.get_dispatch_info();
+		let previous = infos.insert(sample.get_call_name(), (info.class, info.pays_fee));
+		assert!(previous.is_none(), "several samples of `{}`", sample.get_call_name());
+	}
+
+	CallClassification(
+		Call::get_call_names()
+			.iter()
+			.map(|call| CallClass { call, info: infos.get(call).copied() })
+			.collect(),
+	)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		mock::Test,
+		mocking::{assert_golden_file, check_golden_file},
+	};
+	use std::path::Path;
+
+	fn system_call_samples() -> Vec<crate::Call<Test>> {
+		vec![
+			crate::Call::remark { remark: vec![] },
+			crate::Call::set_heap_pages { pages: 64 },
+			crate::Call::set_code { code: vec![] },
+			crate::Call::set_code_without_checks { code: vec![] },
+			crate::Call::set_storage { items: vec![] },
+			crate::Call::kill_storage { keys: vec![] },
+			crate::Call::kill_prefix { prefix: vec![], subkeys: 0 },
+			crate::Call::remark_with_event { remark: vec![] },
+		]
+	}
+
+	#[test]
+	fn dispatch_class_and_pays_fee_can_be_asserted() {
+		crate::assert_dispatch_class!(
+			crate::Call::<Test>::remark { remark: vec![] },
+			DispatchClass::Normal
+		);
+		crate::assert_dispatch_class!(
+			crate::Call::<Test>::set_heap_pages { pages: 64 },
+			DispatchClass::Operational
+		);
+		crate::assert_pays_fee!(crate::Call::<Test>::set_code { code: vec![] }, Pays::Yes);
+	}
+
+	#[test]
+	#[should_panic(expected = "unexpected dispatch class of `crate::Call::<Test>::remark")]
+	fn wrong_dispatch_class_is_reported() {
+		crate::assert_dispatch_class!(
+			crate::Call::<Test>::remark { remark: vec![] },
+			DispatchClass::Operational
+		);
+	}
+
+	#[test]
+	fn system_call_classification_matches_golden_file() {
+		let classification = classify_all_calls(system_call_samples());
+		assert_golden_file(
+			Path::new(env!("CARGO_MANIFEST_DIR")).join("src/mocking/golden/system_calls.txt"),
+			&classification.to_string(),
+		);
+
+		let partial = classify_all_calls(system_call_samples().into_iter().take(1));
+		assert_eq!(partial.0[1], CallClass { call: "set_heap_pages", info: None });
+	}
+
+	#[test]
+	fn golden_file_shows_changed_classification() {
+		let path = std::env::temp_dir().join(format!("system_calls_{}.txt", std::process::id()));
+		let mut classification = classify_all_calls(system_call_samples());
+		std::fs::write(&path, classification.to_string()).unwrap();
+		assert_eq!(check_golden_file(&path, &classification.to_string()), Ok(()));
+
+		classification.0[0].info = Some((DispatchClass::Operational, Pays::No));
+		let diff = check_golden_file(&path, &classification.to_string()).unwrap_err();
+		std::fs::remove_file(&path).unwrap();
+		assert!(diff.contains("-remark                           Normal       Yes\n"), "{}", diff);
+		assert!(diff.contains("+remark                           Operational  No\n"), "{}", diff);
+		assert!(diff.contains(" set_heap_pages                   Operational  Yes\n"), "{}", diff);
+
+		let missing = check_golden_file(&path, "").unwrap_err();
+		assert!(missing.contains("run with `UPDATE_GOLDEN=1` to create it"), "{}", missing);
+	}
+}
@@ -0,0 +1,395 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Dispatch calls while checking which storage they touch and how they change accounts.
+
+use super::{check_invariants, events_prefixes, system_storage_prefix, track_mutations};
+use crate::Config;
+use frame_support::dispatch::{DispatchResultWithPostInfo, PostDispatchInfo};
+use sp_core::hexdisplay::HexDisplay;
+use sp_runtime::traits::Dispatchable;
+use std::{cell::RefCell, collections::BTreeMap, marker::PhantomData};
+
+/// The keys of the System pallet written along with any extrinsic, e.g. the events.
+pub(super) fn always_written<T: Config>() -> Vec<Vec<u8>> {
+	events_prefixes::<T>()
+		.iter()
+		.map(|prefix| prefix.to_vec())
+		.chain(
+			["ExtrinsicCount", "BlockWeight", "AllExtrinsicsLen"]
+				.iter()
+				.map(|storage| system_storage_prefix::<T>(storage).to_vec()),
+		)
+		.collect()
+}
+
+/// Dispatch `call` with `origin` and, if `strict`, panic if it fails but leaves storage mutations
+/// behind outside of the `allowed` prefixes.
+///
+/// The writes of the dispatch are tracked, so the check is proportional to them and not to the
+/// size of the state.
+#[track_caller]
+pub(super) fn dispatch_noop_on_error<Call>(
+	strict: bool,
+	allowed: &[Vec<u8>],
+	call: Call,
+	origin: Call::RuntimeOrigin,
+) -> DispatchResultWithPostInfo
+where
+	Call: Dispatchable<PostInfo = PostDispatchInfo>,
+{
+	if !strict {
+		return call.dispatch(origin)
+	}
+
+	let (result, mut mutations) = track_mutations(|| call.dispatch(origin));
+	if let Err(e) = result {
+		mutations.retain(|key, _| !allowed.iter().any(|prefix| key.starts_with(prefix)));
+		if !mutations.is_empty() {
+			panic!(
+				"the dispatch failed with {:?} but left {} storage mutation(s) behind, is a \
+				storage layer missing? [{}]",
+				e.error,
+				mutations.len(),
+				mutations
+					.keys()
+					.map(|key| format!("0x{}", HexDisplay::from(key)))
+					.collect::<Vec<_>>()
+					.join(", "),
+			)
+		}
+	}
+	result
+}
+
+/// How the reference counters of an account changed during a dispatch, see
+/// [`MockDispatcher::dispatch_with_account_deltas`].
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct AccountRefDeltas {
+	/// The change of the providers.
+	pub providers: i64,
+	/// The change of the consumers.
+	pub consumers: i64,
+	/// The change of the sufficients.
+	pub sufficients: i64,
+}
+
+/// Dispatches calls and checks that those returning an error leave no storage mutation behind.
+///
+/// `assert_noop!` has to be used at each call site; here every dispatch is checked, catching the
+/// calls writing to storage before an error without a storage layer. The keys of the System
+/// pallet written along with any extrinsic, e.g. the events, are always allowed to change. The
+/// same check is done by [`sign_and_execute`](super::sign_and_execute) for every extrinsic.
+///
+/// ```ignore
+/// let dispatcher = MockDispatcher::<Test>::default().allow(&Cache::<Test>::hashed_key());
+/// dispatcher.dispatch(RuntimeCall::Toy(Call::transfer { .. }), RuntimeOrigin::signed(1))?;
+/// ```
+pub struct MockDispatcher<T> {
+	strict_noop_on_error: bool,
+	allowed: Vec<Vec<u8>>,
+	results: RefCell<Vec<String>>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config> Default for MockDispatcher<T> {
+	fn default() -> Self {
+		Self {
+			strict_noop_on_error: true,
+			allowed: always_written::<T>(),
+			results: Default::default(),
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: Config> MockDispatcher<T> {
+	/// Whether to check that failed dispatches don't mutate the storage, `true` by default.
+	pub fn strict_noop_on_error(mut self, strict: bool) -> Self {
+		self.strict_noop_on_error = strict;
+		self
+	}
+
+	/// Allow the keys starting with `prefix` to change when a dispatch fails.
+	pub fn allow(mut self, prefix: &[u8]) -> Self {
+		self.allowed.push(prefix.to_vec());
+		self
+	}
+
+	/// The debug representation of the results of the dispatches so far.
+	pub fn results(&self) -> Vec<String> {
+		self.results.borrow().clone()
+	}
+
+	/// Dispatch `call` with `origin`.
+	///
+	/// # Panics
+	///
+	/// If the dispatch fails and mutated the storage, in strict mode.
+	#[track_caller]
+	pub fn dispatch<Call>(
+		&self,
+		call: Call,
+		origin: Call::RuntimeOrigin,
+	) -> DispatchResultWithPostInfo
+	where
+		Call: Dispatchable<PostInfo = PostDispatchInfo>,
+	{
+		self.dispatch_with(self.strict_noop_on_error, call, origin)
+	}
+
+	/// Dispatch `call` with `origin`, overriding [`Self::strict_noop_on_error`] for this dispatch.
+	#[track_caller]
+	pub fn dispatch_with<Call>(
+		&self,
+		strict_noop_on_error: bool,
+		call: Call,
+		origin: Call::RuntimeOrigin,
+	) -> DispatchResultWithPostInfo
+	where
+		Call: Dispatchable<PostInfo = PostDispatchInfo>,
+	{
+		let result = dispatch_noop_on_error(strict_noop_on_error, &self.allowed, call, origin);
+		let index = {
+			let mut results = self.results.borrow_mut();
+			results.push(format!("{:?}", result));
+			results.len() - 1
+		};
+		check_invariants(&format!("after dispatch {}", index));
+		result
+	}
+
+	/// Dispatch `call` with `origin` like [`Self::dispatch`], also returning the reference
+	/// counter changes of every account whose info changed during the dispatch.
+	///
+	/// Created and reaped accounts are reported with the counters they gained or lost.
+	#[track_caller]
+	pub fn dispatch_with_account_deltas<Call>(
+		&self,
+		call: Call,
+		origin: Call::RuntimeOrigin,
+	) -> (DispatchResultWithPostInfo, BTreeMap<T::AccountId, AccountRefDeltas>)
+	where
+		Call: Dispatchable<PostInfo = PostDispatchInfo>,
+	{
+		let accounts = || crate::Account::<T>::iter().collect::<BTreeMap<_, _>>();
+		let before = accounts();
+		let result = self.dispatch(call, origin);
+		let after = accounts();
+
+		let counters = |info: Option<&crate::AccountInfo<_, _>>| {
+			info.map_or((0, 0, 0), |info| {
+				(i64::from(info.providers), i64::from(info.consumers), i64::from(info.sufficients))
+			})
+		};
+		let deltas = before
+			.keys()
+			.chain(after.keys())
+			.filter(|who| before.get(*who) != after.get(*who))
+			.map(|who| {
+				let (old, new) = (counters(before.get(who)), counters(after.get(who)));
+				let deltas = AccountRefDeltas {
+					providers: new.0 - old.0,
+					consumers: new.1 - old.1,
+					sufficients: new.2 - old.2,
+				};
+				(who.clone(), deltas)
+			})
+			.collect();
+		(result, deltas)
+	}
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System, Test};
+	use frame_support::{
+		dispatch::DispatchInfo,
+		storage::{storage_prefix, unhashed},
+		traits::CrateVersion,
+	};
+	use sp_runtime::{DispatchError, DispatchResult};
+
+	mod strict_noop {
+		use super::*;
+		use crate::mock::RuntimeOrigin;
+
+		pub const KEY: &[u8] = b"strict_noop_key";
+
+		/// A toy dispatchable writing to storage before failing, within a storage layer or not.
+		pub struct Call {
+			pub transactional: bool,
+		}
+
+		fn write_then_fail() -> DispatchResult {
+			unhashed::put(KEY, &1u32);
+			System::deposit_event(crate::Event::Remarked { sender: 1, hash: Default::default() });
+			Err(DispatchError::Other("failed after writing"))
+		}
+
+		impl Dispatchable for Call {
+			type RuntimeOrigin = RuntimeOrigin;
+			type Config = Test;
+			type Info = DispatchInfo;
+			type PostInfo = PostDispatchInfo;
+
+			fn dispatch(self, _: RuntimeOrigin) -> DispatchResultWithPostInfo {
+				if self.transactional {
+					frame_support::storage::with_storage_layer(write_then_fail)?;
+				} else {
+					write_then_fail()?;
+				}
+				Ok(().into())
+			}
+		}
+	}
+
+	#[test]
+	#[should_panic(
+		expected = "the dispatch failed with Other(\"failed after writing\") but left 1 \
+		storage mutation(s) behind, is a storage layer missing? [0x7374726963745f6e6f6f705f6b6579]"
+	)]
+	fn writes_before_an_error_are_caught() {
+		use crate::mock::RuntimeOrigin;
+		use strict_noop::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let _ = MockDispatcher::<Test>::default()
+				.dispatch(Call { transactional: false }, RuntimeOrigin::root());
+		});
+	}
+
+	#[test]
+	fn transactional_errors_and_overrides_pass() {
+		use crate::mock::RuntimeOrigin;
+		use strict_noop::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let dispatcher = MockDispatcher::<Test>::default();
+			assert!(dispatcher
+				.dispatch(Call { transactional: true }, RuntimeOrigin::root())
+				.is_err());
+			assert_eq!(unhashed::get::<u32>(KEY), None);
+			assert!(System::events().is_empty());
+
+			assert!(dispatcher
+				.dispatch_with(false, Call { transactional: false }, RuntimeOrigin::root())
+				.is_err());
+			assert_eq!(unhashed::get::<u32>(KEY), Some(1));
+
+			let lenient = MockDispatcher::<Test>::default().strict_noop_on_error(false);
+			assert!(lenient
+				.dispatch(Call { transactional: false }, RuntimeOrigin::root())
+				.is_err());
+			// The event is always allowed.
+			let allowing = MockDispatcher::<Test>::default().allow(KEY);
+			unhashed::kill(KEY);
+			assert!(allowing
+				.dispatch(Call { transactional: false }, RuntimeOrigin::root())
+				.is_err());
+		});
+	}
+
+	pub mod renamed_system {
+		use super::*;
+		use crate as frame_system;
+		use frame_support::derive_impl;
+
+		type Block = frame_system::mocking::MockBlock<Test>;
+
+		frame_support::construct_runtime!(
+			pub enum Test
+			{
+				System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+			}
+		);
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Test {
+			type Block = Block;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = RenamedPalletInfo;
+		}
+
+		/// Names the System pallet `Sys`, as a runtime not using `construct_runtime!` could.
+		pub struct RenamedPalletInfo;
+
+		impl frame_support::traits::PalletInfo for RenamedPalletInfo {
+			fn index<P: 'static>() -> Option<usize> {
+				<PalletInfo as frame_support::traits::PalletInfo>::index::<P>()
+			}
+
+			fn name<P: 'static>() -> Option<&'static str> {
+				<PalletInfo as frame_support::traits::PalletInfo>::name::<P>().map(|name| {
+					if name == "System" {
+						"Sys"
+					} else {
+						name
+					}
+				})
+			}
+
+			fn module_name<P: 'static>() -> Option<&'static str> {
+				<PalletInfo as frame_support::traits::PalletInfo>::module_name::<P>()
+			}
+
+			fn crate_version<P: 'static>() -> Option<CrateVersion> {
+				<PalletInfo as frame_support::traits::PalletInfo>::crate_version::<P>()
+			}
+		}
+
+		/// A toy dispatchable depositing an event before failing, without a storage layer.
+		pub struct Call;
+
+		impl Dispatchable for Call {
+			type RuntimeOrigin = RuntimeOrigin;
+			type Config = Test;
+			type Info = DispatchInfo;
+			type PostInfo = PostDispatchInfo;
+
+			fn dispatch(self, _: RuntimeOrigin) -> DispatchResultWithPostInfo {
+				System::deposit_event(crate::Event::Remarked {
+					sender: 1,
+					hash: Default::default(),
+				});
+				Err(DispatchError::Other("failed after an event").into())
+			}
+		}
+	}
+
+	#[test]
+	fn allowed_keys_follow_the_name_of_the_system_pallet() {
+		use renamed_system::{Call, RuntimeOrigin, System, Test};
+		use sp_runtime::BuildStorage;
+
+		let mut ext: sp_io::TestExternalities =
+			crate::GenesisConfig::<Test>::default().build_storage().unwrap().into();
+		ext.execute_with(|| {
+			System::set_block_number(1);
+			assert_eq!(crate::Events::<Test>::hashed_key(), storage_prefix(b"Sys", b"Events"));
+
+			let dispatcher = MockDispatcher::<Test>::default();
+			assert!(dispatcher.dispatch(Call, RuntimeOrigin::root()).is_err());
+			assert_eq!(System::events().len(), 1);
+		});
+	}
+}
@@ -0,0 +1,211 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Guard the variant indices of an enum against a fixture.
+
+use super::UPDATE_GOLDEN_ENV;
+use scale_info::{TypeDef, TypeInfo};
+use std::{collections::BTreeMap, path::Path};
+
+/// The name and index of each variant of the enum `E`, in declaration order.
+///
+/// # Panics
+///
+/// If `E` isn't an enum.
+pub fn enum_variants<E: TypeInfo + 'static>() -> Vec<(&'static str, u8)> {
+	match E::type_info().type_def {
+		TypeDef::Variant(def) => def.variants.iter().map(|v| (v.name, v.index)).collect(),
+		_ => panic!("`{}` is not an enum", std::any::type_name::<E>()),
+	}
+}
+
+/// Check the variant indices of the enum `E`, e.g. the `Event` or `Error` of a pallet, against
+/// the fixture at `path`.
+///
+/// The fixture is written by the first run, when it doesn't exist yet, and if the
+/// [`UPDATE_GOLDEN_ENV`] environment variable is set to re-bless it. Otherwise, the inserted,
+/// removed and moved variants are returned on mismatch.
+pub fn check_enum_encoding<E: TypeInfo + 'static>(path: impl AsRef<Path>) -> Result<(), String> {
+	check_enum_encoding_with::<E>(path.as_ref(), std::env::var_os(UPDATE_GOLDEN_ENV).is_some())
+}
+
+fn check_enum_encoding_with<E: TypeInfo + 'static>(path: &Path, bless: bool) -> Result<(), String> {
+	let variants = enum_variants::<E>();
+	let fixture = variants
+		.iter()
+		.map(|(name, index)| format!("{} {}\n", index, name))
+		.collect::<String>();
+	if bless || !path.exists() {
+		return std::fs::create_dir_all(path.parent().unwrap_or(Path::new("")))
+			.and_then(|_| std::fs::write(path, fixture))
+			.map_err(|e| format!("failed to write fixture {}: {}", path.display(), e))
+	}
+
+	let expected = std::fs::read_to_string(path)
+		.map_err(|e| format!("failed to read fixture {}: {}", path.display(), e))?;
+	let expected = expected
+		.lines()
+		.map(|line| {
+			let (index, name) = line.split_once(' ')?;
+			Some((name.to_string(), index.parse::<u8>().ok()?))
+		})
+		.collect::<Option<BTreeMap<_, _>>>()
+		.ok_or_else(|| format!("malformed fixture {}", path.display()))?;
+	let actual = variants
+		.iter()
+		.map(|(name, index)| (name.to_string(), *index))
+		.collect::<BTreeMap<_, _>>();
+
+	let mut changes = Vec::new();
+	for (name, index) in &actual {
+		match expected.get(name) {
+			None => changes.push(format!("inserted `{}` at index {}", name, index)),
+			Some(old) if old != index =>
+				changes.push(format!("moved `{}` from index {} to {}", name, old, index)),
+			Some(_) => {},
+		}
+	}
+	for (name, index) in &expected {
+		if !actual.contains_key(name) {
+			changes.push(format!("removed `{}` from index {}", name, index));
+		}
+	}
+	if changes.is_empty() {
+		return Ok(())
+	}
+	Err(format!(
+		"the encoding of `{}` changed, run with `{}=1` to re-bless {} if this is intended:\n{}",
+		std::any::type_name::<E>(),
+		UPDATE_GOLDEN_ENV,
+		path.display(),
+		changes.join("\n"),
+	))
+}
+
+/// The fixture file of the enum `E` in `dir`, named after its type path.
+pub fn enum_encoding_fixture<E: TypeInfo + 'static>(dir: impl AsRef<Path>) -> std::path::PathBuf {
+	dir.as_ref().join(format!("{}.txt", E::type_info().path.segments.join(".")))
+}
+
+/// Guard the variant indices of an enum, e.g. the `Event` or `Error` of a pallet, against the
+/// fixture named after its type path in `encoding_guards/` of the calling crate, or in the given
+/// directory. The fixture is written by the first run, see
+/// [`check_enum_encoding`](crate::mocking::check_enum_encoding).
+///
+/// ```ignore
+/// #[test]
+/// fn event_encoding_is_stable() {
+///     frame_system::generate_enum_encoding_guard!(pallet_balances::Event<Test>);
+/// }
+/// ```
+#[macro_export]
+macro_rules! generate_enum_encoding_guard {
+	( $enum:ty $(,)? ) => {
+		$crate::generate_enum_encoding_guard!(
+			$enum,
+			::core::concat!(::core::env!("CARGO_MANIFEST_DIR"), "/encoding_guards")
+		)
+	};
+	( $enum:ty, $dir:expr $(,)? ) => {
+		if let ::core::result::Result::Err(e) =
+			$crate::mocking::check_enum_encoding::<$enum>($crate::mocking::enum_encoding_fixture::<
+				$enum,
+			>($dir))
+		{
+			::core::panic!("{}", e)
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::Test;
+
+	mod encoding_guard {
+		use super::*;
+
+		pub mod before {
+			#[derive(scale_info::TypeInfo)]
+			#[allow(dead_code)]
+			pub enum Event {
+				Deposited,
+				Withdrawn,
+				Slashed,
+			}
+		}
+
+		pub mod after {
+			#[derive(scale_info::TypeInfo)]
+			#[allow(dead_code)]
+			pub enum Event {
+				Deposited,
+				Slashed,
+				Withdrawn,
+				Reaped,
+			}
+		}
+
+		pub fn fixture() -> std::path::PathBuf {
+			let dir = std::env::temp_dir().join(format!("encoding_guard_{}", std::process::id()));
+			enum_encoding_fixture::<before::Event>(dir)
+		}
+	}
+
+	#[test]
+	fn system_event_and_error_encodings_are_stable() {
+		let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/src/mocking/golden");
+		crate::generate_enum_encoding_guard!(crate::Event<Test>, dir);
+		crate::generate_enum_encoding_guard!(crate::Error<Test>, dir);
+		assert_eq!(enum_variants::<crate::Error<Test>>()[0], ("InvalidSpecName", 0));
+	}
+
+	#[test]
+	fn reordered_variants_are_detected_until_reblessed() {
+		use encoding_guard::*;
+
+		let path = fixture();
+		assert!(path.ends_with(
+			"frame_system.mocking.ext.encoding_guard.tests.encoding_guard.before.Event.txt"
+		));
+		// The first run writes the fixture.
+		assert!(!path.exists());
+		assert_eq!(check_enum_encoding_with::<before::Event>(&path, false), Ok(()));
+		assert_eq!(
+			std::fs::read_to_string(&path).unwrap(),
+			"0 Deposited\n1 Withdrawn\n2 Slashed\n"
+		);
+		assert_eq!(check_enum_encoding_with::<before::Event>(&path, false), Ok(()));
+
+		let error = check_enum_encoding_with::<after::Event>(&path, false).unwrap_err();
+		assert!(error.contains("run with `UPDATE_GOLDEN=1` to re-bless"), "{}", error);
+		assert!(
+			error.ends_with(
+				"inserted `Reaped` at index 3\nmoved `Slashed` from index 2 to 1\n\
+				 moved `Withdrawn` from index 1 to 2"
+			),
+			"{}",
+			error
+		);
+
+		assert_eq!(check_enum_encoding_with::<after::Event>(&path, true), Ok(()));
+		assert_eq!(check_enum_encoding_with::<after::Event>(&path, false), Ok(()));
+		let error = check_enum_encoding_with::<before::Event>(&path, false).unwrap_err();
+		assert!(error.ends_with("removed `Reaped` from index 3"), "{}", error);
+		std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+	}
+}
@@ -0,0 +1,326 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Check that two implementations of a call have the same observable effects.
+
+use super::{events_prefixes, first_difference, storage_mutations, storage_pairs};
+use crate::Config;
+use sp_core::hexdisplay::HexDisplay;
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt,
+};
+
+/// The observable effects of a call, see [`check_equivalent_dispatch`].
+struct DispatchTrace<R> {
+	result: R,
+	events: Vec<String>,
+	mutations: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+/// A divergence between two implementations found by [`check_equivalent_dispatch`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DispatchDivergence {
+	/// The debug representation of the input triggering the divergence.
+	pub input: String,
+	/// What diverged: the `result`, the `events` or the `storage`.
+	pub observable: &'static str,
+	/// The differing lines, prefixed with `-` for the old implementation and `+` for the new one.
+	pub diff: Vec<String>,
+}
+
+impl fmt::Display for DispatchDivergence {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "the {} diverged for input `{}`:", self.observable, self.input)?;
+		self.diff.iter().try_for_each(|line| writeln!(f, "{}", line))
+	}
+}
+
+/// Check that `old` and `new` are observationally equivalent on all `inputs`.
+///
+/// For each input, both implementations are run from the same state, rolled back afterwards, and
+/// their results, emitted events and storage mutations are compared. The keys starting with one
+/// of the `allowed` prefixes may be mutated differently, e.g. a cache. The inputs are typically
+/// an origin and the arguments of the call; the adversarial generators of
+/// [`adversarial`](crate::mocking::adversarial) can be passed directly, their descriptions being
+/// part of the reported input.
+///
+/// Must be called within externalities; returns the first divergence.
+pub fn check_equivalent_dispatch<T: Config, I: Clone + fmt::Debug, R: PartialEq + fmt::Debug>(
+	old: impl Fn(I) -> R,
+	new: impl Fn(I) -> R,
+	inputs: impl IntoIterator<Item = I>,
+	allowed: &[&[u8]],
+) -> Result<(), DispatchDivergence> {
+	let events_prefixes = events_prefixes::<T>();
+	let trace = |implementation: &dyn Fn(I) -> R, input: I| {
+		sp_io::storage::start_transaction();
+		let before = storage_pairs().into_iter().collect::<BTreeMap<_, _>>();
+		let previous_events = crate::Pallet::<T>::read_events_no_consensus().count();
+
+		let result = implementation(input);
+
+		let events = crate::Pallet::<T>::read_events_no_consensus()
+			.skip(previous_events)
+			.map(|record| format!("{:?}", record.event))
+			.collect();
+		let mut mutations = storage_mutations(&before);
+		mutations.retain(|key, _| {
+			!events_prefixes
+				.iter()
+				.map(|prefix| &prefix[..])
+				.chain(allowed.iter().copied())
+				.any(|prefix| key.starts_with(prefix))
+		});
+		sp_io::storage::rollback_transaction();
+		DispatchTrace { result, events, mutations }
+	};
+
+	for input in inputs {
+		let (old, new) = (trace(&old, input.clone()), trace(&new, input.clone()));
+		let diverged = |observable, diff| {
+			Err(DispatchDivergence { input: format!("{:?}", input), observable, diff })
+		};
+
+		if old.result != new.result {
+			return diverged(
+				"result",
+				vec![format!("-{:?}", old.result), format!("+{:?}", new.result)],
+			)
+		}
+		if old.events != new.events {
+			let (old, new) = first_difference(&old.events, &new.events);
+			let diff =
+				old.map(|e| format!("-{}", e)).into_iter().chain(new.map(|e| format!("+{}", e)));
+			return diverged("events", diff.collect())
+		}
+		if old.mutations != new.mutations {
+			let mutation = |key: &[u8], value: Option<&Option<Vec<u8>>>| match value {
+				None => format!("0x{} unchanged", HexDisplay::from(&key)),
+				Some(None) => format!("0x{} removed", HexDisplay::from(&key)),
+				Some(Some(value)) =>
+					format!("0x{} => 0x{}", HexDisplay::from(&key), HexDisplay::from(value)),
+			};
+			let diff = old
+				.mutations
+				.keys()
+				.chain(new.mutations.keys())
+				.collect::<BTreeSet<_>>()
+				.into_iter()
+				.filter(|key| old.mutations.get(*key) != new.mutations.get(*key))
+				.flat_map(|key| {
+					[
+						format!("-{}", mutation(key, old.mutations.get(key))),
+						format!("+{}", mutation(key, new.mutations.get(key))),
+					]
+				});
+			return diverged("storage", diff.collect())
+		}
+	}
+	Ok(())
+}
+
+/// Assert that two implementations of a dispatchable are observationally equivalent, see
+/// [`check_equivalent_dispatch`](crate::mocking::check_equivalent_dispatch).
+///
+/// ```ignore
+/// frame_system::assert_equivalent_dispatch!(Test;
+///     |(origin, amount)| Pallet::<Test>::transfer_old(origin, amount),
+///     |(origin, amount)| Pallet::<Test>::transfer(origin, amount),
+///     inputs,
+///     allow: [Cache::<Test>::hashed_key()],
+/// );
+/// ```
+#[macro_export]
+macro_rules! assert_equivalent_dispatch {
+	(
+		$runtime:ty; $old:expr, $new:expr, $inputs:expr
+		$(, allow: [ $( $allowed:expr ),* $(,)? ] )? $(,)?
+	) => {
+		if let ::core::result::Result::Err(divergence) =
+			$crate::mocking::check_equivalent_dispatch::<$runtime, _, _>(
+				$old,
+				$new,
+				$inputs,
+				&[ $( $( &$allowed[..] ),* )? ],
+			)
+		{
+			::core::panic!(
+				"`{}` and `{}` are not equivalent: {}",
+				::core::stringify!($old),
+				::core::stringify!($new),
+				divergence,
+			)
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System, Test};
+	use sp_runtime::{DispatchError, DispatchResult, Perbill};
+
+	mod equivalence {
+		use super::*;
+		use frame_support::Twox64Concat;
+
+		#[frame_support::storage_alias]
+		pub type Fees = StorageMap<Toy, Twox64Concat, u64, u64>;
+
+		#[frame_support::storage_alias]
+		pub type FeeCache = StorageValue<Toy, u64>;
+
+		pub fn charge_old(who: u64, amount: u64) -> DispatchResult {
+			ensure_amount(amount)?;
+			Fees::insert(who, amount * 3 / 10);
+			Ok(())
+		}
+
+		pub fn charge_new(who: u64, amount: u64) -> DispatchResult {
+			ensure_amount(amount)?;
+			let fee = Perbill::from_percent(30).mul_floor(amount);
+			Fees::insert(who, fee);
+			FeeCache::put(fee);
+			Ok(())
+		}
+
+		pub fn charge_rounding_up(who: u64, amount: u64) -> DispatchResult {
+			ensure_amount(amount)?;
+			Fees::insert(who, Perbill::from_percent(30).mul_ceil(amount));
+			Ok(())
+		}
+
+		pub fn charge_with_event(who: u64, amount: u64) -> DispatchResult {
+			charge_old(who, amount)?;
+			System::deposit_event(crate::Event::Remarked { sender: who, hash: Default::default() });
+			Ok(())
+		}
+
+		fn ensure_amount(amount: u64) -> DispatchResult {
+			if amount > 1_000 {
+				return Err(DispatchError::Other("amount too high"))
+			}
+			Ok(())
+		}
+
+		pub fn inputs() -> Vec<(u64, u64)> {
+			vec![(1, 10), (2, 20), (3, 2_000)]
+		}
+	}
+
+	#[test]
+	fn equivalent_implementations_pass() {
+		use equivalence::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			crate::assert_equivalent_dispatch!(Test;
+				|(who, amount)| charge_old(who, amount),
+				|(who, amount)| charge_new(who, amount),
+				inputs(),
+				allow: [FeeCache::hashed_key()],
+			);
+			// Both implementations were rolled back.
+			assert_eq!(Fees::iter().count(), 0);
+		});
+	}
+
+	#[test]
+	fn divergent_rounding_is_reported_with_the_input() {
+		use crate::mocking::adversarial::zero_and_one_values;
+		use equivalence::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let divergence = check_equivalent_dispatch::<Test, _, _>(
+				|(_, amount)| charge_old(7, amount),
+				|(_, amount)| charge_rounding_up(7, amount),
+				zero_and_one_values::<u64>(),
+				&[],
+			)
+			.unwrap_err();
+			assert_eq!(divergence.input, "(\"one\", 1)");
+			assert_eq!(divergence.observable, "storage");
+			let key = HexDisplay::from(&Fees::hashed_key_for(7)).to_string();
+			assert_eq!(
+				divergence.diff,
+				vec![
+					format!("-0x{} => 0x{}", key, "0000000000000000"),
+					format!("+0x{} => 0x{}", key, "0100000000000000")
+				]
+			);
+		});
+	}
+
+	#[test]
+	fn unexpected_mutations_and_events_are_reported() {
+		use equivalence::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let divergence = check_equivalent_dispatch::<Test, _, _>(
+				|(who, amount)| charge_old(who, amount),
+				|(who, amount)| charge_new(who, amount),
+				inputs(),
+				&[],
+			)
+			.unwrap_err();
+			let key = HexDisplay::from(&FeeCache::hashed_key()).to_string();
+			assert_eq!(
+				divergence.to_string(),
+				format!(
+					"the storage diverged for input `(1, 10)`:\n\
+					-0x{} unchanged\n\
+					+0x{} => 0x0300000000000000\n",
+					key, key
+				)
+			);
+
+			let divergence = check_equivalent_dispatch::<Test, _, _>(
+				|(who, amount)| charge_old(who, amount),
+				|(who, amount)| charge_with_event(who, amount),
+				inputs(),
+				&[],
+			)
+			.unwrap_err();
+			assert_eq!(divergence.observable, "events");
+			assert!(
+				divergence.diff[0].starts_with("+RuntimeEvent::System(Event::Remarked { sender: 1"),
+				"{:?}",
+				divergence.diff
+			);
+		});
+	}
+
+	#[test]
+	#[should_panic(
+		expected = "the result diverged for input `(3, 2000)`:\n-Err(Other(\"amount too high\"))\n+Ok(())"
+	)]
+	fn divergent_results_fail_the_assertion() {
+		use equivalence::*;
+
+		new_test_ext().execute_with(|| {
+			crate::assert_equivalent_dispatch!(Test;
+				|(who, amount)| charge_old(who, amount),
+				|(who, amount)| charge_new(who, amount.min(1_000)),
+				inputs(),
+				allow: [FeeCache::hashed_key()],
+			);
+		});
+	}
+}
@@ -0,0 +1,498 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Build the externalities of a mock runtime, and compare its storage root across state versions.
+
+use super::storage_pairs;
+use crate::{pallet_prelude::BlockNumberFor, Config};
+use codec::Encode;
+use sp_core::{hexdisplay::HexDisplay, storage::TRIE_VALUE_NODE_THRESHOLD};
+use sp_runtime::{traits::One, BuildStorage, Saturating, StateVersion};
+use std::collections::BTreeMap;
+
+type AccountInfoOf<T> = crate::AccountInfo<<T as Config>::Nonce, <T as Config>::AccountData>;
+
+/// Builds [`sp_io::TestExternalities`] for a mock runtime.
+///
+/// Unless created with [`Self::empty`], the default genesis of `frame_system` is part of the
+/// state. Further genesis configs are assimilated in the order they are added, and the accounts
+/// and storage items are written on top of them.
+pub struct MockExternalitiesBuilder<T: Config> {
+	genesis: Vec<(String, Box<dyn BuildStorage>)>,
+	state_version: StateVersion,
+	starting_block: Option<BlockNumberFor<T>>,
+	accounts: Vec<(T::AccountId, AccountInfoOf<T>)>,
+	storage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl<T: Config> Default for MockExternalitiesBuilder<T> {
+	fn default() -> Self {
+		Self::empty().with_genesis(crate::GenesisConfig::<T>::default())
+	}
+}
+
+impl<T: Config> MockExternalitiesBuilder<T> {
+	/// Create a new builder.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Create a new builder without any genesis config, not even the one of `frame_system`.
+	pub fn empty() -> Self {
+		Self {
+			genesis: Vec::new(),
+			state_version: StateVersion::default(),
+			starting_block: None,
+			accounts: Vec::new(),
+			storage: Vec::new(),
+		}
+	}
+
+	/// Build the externalities, including the genesis state, with the given state version.
+	pub fn state_version(mut self, state_version: StateVersion) -> Self {
+		self.state_version = state_version;
+		self
+	}
+
+	/// Initialize the block `n` with [`Pallet::initialize`](crate::Pallet::initialize) once the
+	/// externalities are built, so events are deposited from the start.
+	///
+	/// By default, the externalities are at the genesis block, which isn't initialized.
+	pub fn starting_block(mut self, n: BlockNumberFor<T>) -> Self {
+		self.starting_block = Some(n);
+		self
+	}
+
+	/// Set the account info of `who`.
+	pub fn with_account(mut self, who: T::AccountId, info: AccountInfoOf<T>) -> Self {
+		self.accounts.push((who, info));
+		self
+	}
+
+	/// Set the raw storage item at `key`.
+	pub fn with_storage(mut self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Self {
+		self.storage.push((key.into(), value.into()));
+		self
+	}
+
+	/// Add the genesis config of a pallet.
+	pub fn with_genesis<C: BuildStorage + 'static>(self, config: C) -> Self {
+		self.with_named_genesis(std::any::type_name::<C>(), config)
+	}
+
+	/// Add the genesis config of a pallet, naming it `name` in errors.
+	pub fn with_named_genesis(
+		mut self,
+		name: impl Into<String>,
+		config: impl BuildStorage + 'static,
+	) -> Self {
+		self.genesis.push((name.into(), Box::new(config)));
+		self
+	}
+
+	/// Build the externalities.
+	///
+	/// Returns an error naming the genesis config that failed to build.
+	pub fn try_build(self) -> Result<sp_io::TestExternalities, String> {
+		let mut storage = Default::default();
+		for (name, config) in &self.genesis {
+			config
+				.assimilate_storage(&mut storage)
+				.map_err(|e| format!("failed to build the genesis of `{}`: {}", name, e))?;
+		}
+		for (who, info) in &self.accounts {
+			storage.top.insert(crate::Account::<T>::hashed_key_for(who), info.encode());
+		}
+		storage.top.extend(self.storage.iter().cloned());
+
+		// `TestExternalities` always sets the code, keep the one of the genesis.
+		let code = storage.top.remove(sp_core::storage::well_known_keys::CODE).unwrap_or_default();
+		let mut ext =
+			sp_io::TestExternalities::new_with_code_and_state(&code, storage, self.state_version);
+		if let Some(n) = self.starting_block {
+			ext.execute_with(|| {
+				let parent_hash = crate::Pallet::<T>::block_hash(n.saturating_sub(One::one()));
+				crate::Pallet::<T>::initialize(&n, &parent_hash, &Default::default());
+			});
+		}
+		Ok(ext)
+	}
+
+	/// Build the externalities.
+	///
+	/// # Panics
+	///
+	/// If any genesis config fails to build, see [`Self::try_build`].
+	pub fn build(self) -> sp_io::TestExternalities {
+		self.try_build().unwrap_or_else(|e| panic!("{}", e))
+	}
+
+	/// Build the externalities over a trie backend all changes are committed to, so the storage
+	/// proofs of the accesses can be measured.
+	///
+	/// # Panics
+	///
+	/// If any genesis config fails to build, see [`Self::try_build`].
+	pub fn with_trie_backend(self) -> TrieExternalities {
+		TrieExternalities(self.build())
+	}
+}
+
+/// Create a [`MockExternalitiesBuilder`] from a list of genesis configs.
+///
+/// Each entry names the genesis config type of a pallet (e.g. the `SystemConfig` alias generated
+/// by `construct_runtime!`) and is either `default` or the fields to set, all others taking their
+/// default value. The configs are assimilated in the given order, and only those are part of the
+/// state.
+///
+/// ```ignore
+/// let mut ext = frame_system::genesis! { Test;
+///     SystemConfig: default,
+///     BalancesConfig: { balances: vec![(1, 100)] },
+/// }
+/// .state_version(StateVersion::V0)
+/// .build();
+/// ```
+#[macro_export]
+macro_rules! genesis {
+	( $runtime:ty; $( $config:ident : $value:tt ),* $(,)? ) => {
+		$crate::mocking::MockExternalitiesBuilder::<$runtime>::empty()
+			$( .with_named_genesis(::core::stringify!($config), $crate::genesis!(@config $config $value)) )*
+	};
+	(@config $config:ident default) => {
+		<$config as ::core::default::Default>::default()
+	};
+	(@config $config:ident { $( $field:ident : $value:expr ),* $(,)? }) => {
+		{
+			#[allow(clippy::needless_update)]
+			let config = $config { $( $field: $value, )* ..::core::default::Default::default() };
+			config
+		}
+	};
+}
+
+/// Externalities whose state lives in a trie, built by
+/// [`MockExternalitiesBuilder::with_trie_backend`].
+///
+/// [`sp_io::TestExternalities`] keeps changes in an overlay, which reads are served from without
+/// touching the trie. Here, the changes are committed to the trie after each closure, so the
+/// storage proofs recorded by [`Self::recorded_proof_size`] and [`Self::recorded_trie_nodes`]
+/// are the ones a block doing the same accesses would need.
+pub struct TrieExternalities(sp_io::TestExternalities);
+
+impl TrieExternalities {
+	/// Execute `execute` and commit its changes to the trie.
+	///
+	/// # Panics
+	///
+	/// If `execute` leaves a storage transaction open.
+	pub fn execute_with<R>(&mut self, execute: impl FnOnce() -> R) -> R {
+		let result = self.0.execute_with(execute);
+		self.0.commit_all().expect("changes of the closure can be committed");
+		result
+	}
+
+	/// The encoded size and the number of nodes of the storage proof of `execute`.
+	fn prove(&mut self, execute: impl FnOnce()) -> (usize, usize) {
+		let ((), proof) = self.0.execute_and_prove(execute);
+		self.0.commit_all().expect("changes of the closure can be committed");
+		(proof.encoded_size(), proof.iter_nodes().count())
+	}
+
+	/// Execute `execute` and return the encoded size of the storage proof of its accesses,
+	/// including the ones to child tries.
+	///
+	/// Each call records a proof of its own.
+	pub fn recorded_proof_size(&mut self, execute: impl FnOnce()) -> usize {
+		self.prove(execute).0
+	}
+
+	/// Execute `execute` and return the number of trie nodes in the storage proof of its
+	/// accesses, including the ones to child tries.
+	///
+	/// Each call records a proof of its own.
+	pub fn recorded_trie_nodes(&mut self, execute: impl FnOnce()) -> usize {
+		self.prove(execute).1
+	}
+
+	/// The underlying externalities.
+	pub fn into_inner(self) -> sp_io::TestExternalities {
+		self.0
+	}
+}
+
+/// How the storage root after a scenario depends on the state version.
+///
+/// See [`state_version_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateVersionReport {
+	/// The storage root with [`StateVersion::V0`].
+	pub root_v0: Vec<u8>,
+	/// The storage root with [`StateVersion::V1`].
+	pub root_v1: Vec<u8>,
+	/// Keys written by the scenario whose value is at least [`TRIE_VALUE_NODE_THRESHOLD`] bytes
+	/// long and is thus only inlined into the trie with [`StateVersion::V0`].
+	pub value_node_keys: Vec<Vec<u8>>,
+}
+
+impl StateVersionReport {
+	/// Whether both state versions lead to the same storage root.
+	pub fn is_stable(&self) -> bool {
+		self.root_v0 == self.root_v1
+	}
+}
+
+/// Run `scenario` in externalities built with each state version and compare the storage roots.
+pub fn state_version_report<T: Config>(scenario: impl Fn()) -> StateVersionReport {
+	let run = |state_version| {
+		MockExternalitiesBuilder::<T>::new()
+			.state_version(state_version)
+			.build()
+			.execute_with(|| {
+				let before = storage_pairs().into_iter().collect::<BTreeMap<_, _>>();
+				scenario();
+				let value_node_keys = storage_pairs()
+					.into_iter()
+					.filter(|(key, value)| {
+						value.len() >= TRIE_VALUE_NODE_THRESHOLD as usize &&
+							before.get(key) != Some(value)
+					})
+					.map(|(key, _)| key)
+					.collect::<Vec<_>>();
+				(sp_io::storage::root(state_version), value_node_keys)
+			})
+	};
+
+	let (root_v0, _) = run(StateVersion::V0);
+	let (root_v1, value_node_keys) = run(StateVersion::V1);
+	StateVersionReport { root_v0, root_v1, value_node_keys }
+}
+
+/// Assert that `scenario` leads to the same storage root with both state versions.
+///
+/// # Panics
+///
+/// If the roots differ, naming the written keys whose values are stored differently.
+pub fn assert_root_stable_across_versions<T: Config>(scenario: impl Fn()) {
+	let report = state_version_report::<T>(scenario);
+	if !report.is_stable() {
+		let keys = report
+			.value_node_keys
+			.iter()
+			.map(|key| format!("0x{}", HexDisplay::from(key)))
+			.collect::<Vec<_>>();
+		panic!(
+			"storage root differs between state versions (V0: 0x{}, V1: 0x{}), values of keys \
+			 [{}] reach the inline threshold of {} bytes",
+			HexDisplay::from(&report.root_v0),
+			HexDisplay::from(&report.root_v1),
+			keys.join(", "),
+			TRIE_VALUE_NODE_THRESHOLD,
+		);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{System, Test};
+	use frame_support::storage::{storage_prefix, unhashed};
+	use sp_core::storage::ChildInfo;
+
+	#[test]
+	fn builder_uses_requested_state_version() {
+		let large = vec![1u8; TRIE_VALUE_NODE_THRESHOLD as usize];
+		let roots = [StateVersion::V0, StateVersion::V1].map(|state_version| {
+			MockExternalitiesBuilder::<Test>::new()
+				.state_version(state_version)
+				.build()
+				.execute_with(|| {
+					unhashed::put_raw(b"large", &large);
+					sp_io::storage::root(state_version)
+				})
+		});
+
+		let report = state_version_report::<Test>(|| unhashed::put_raw(b"large", &large));
+		assert_eq!(roots, [report.root_v0.clone(), report.root_v1.clone()]);
+		assert!(!report.is_stable());
+		assert_eq!(report.value_node_keys, vec![b"large".to_vec()]);
+	}
+
+	#[test]
+	fn builder_starts_at_the_requested_block() {
+		use crate::mock::{RuntimeEvent, SysEvent};
+
+		let info = crate::AccountInfo { nonce: 3, providers: 1, ..Default::default() };
+		MockExternalitiesBuilder::<Test>::new()
+			.starting_block(5)
+			.with_account(1, info.clone())
+			.with_storage(b":toy".to_vec(), b"value".to_vec())
+			.build()
+			.execute_with(|| {
+				assert_eq!(System::block_number(), 5);
+				assert_eq!(System::parent_hash(), System::block_hash(4));
+				assert_eq!(crate::Account::<Test>::get(1), info);
+				assert_eq!(unhashed::get_raw(b":toy"), Some(b"value".to_vec()));
+
+				System::deposit_event(SysEvent::CodeUpdated);
+				assert_eq!(System::events().len(), 1);
+				assert_eq!(System::events()[0].event, RuntimeEvent::System(SysEvent::CodeUpdated));
+			});
+
+		// Without a starting block, the genesis block isn't initialized and events are dropped.
+		MockExternalitiesBuilder::<Test>::new().build().execute_with(|| {
+			assert_eq!(System::block_number(), 0);
+			System::deposit_event(SysEvent::CodeUpdated);
+			assert_eq!(System::event_count(), 0);
+		});
+	}
+
+	#[test]
+	fn small_values_are_stable_across_versions() {
+		assert_root_stable_across_versions::<Test>(|| {
+			unhashed::put_raw(b"small", &[1u8; TRIE_VALUE_NODE_THRESHOLD as usize - 1]);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "values of keys [0x6c61726765] reach the inline threshold")]
+	fn large_values_are_reported() {
+		assert_root_stable_across_versions::<Test>(|| {
+			unhashed::put_raw(b"small", &[1u8; 4]);
+			unhashed::put_raw(b"large", &[1u8; 64]);
+		});
+	}
+
+	mod genesis {
+		use super::*;
+
+		pub const TOY_VALUE: &[u8] = b"toy_value";
+
+		/// The genesis config of a toy pallet.
+		#[derive(Default)]
+		pub struct ToyConfig {
+			pub value: u32,
+			pub extra: u32,
+		}
+
+		impl BuildStorage for ToyConfig {
+			fn assimilate_storage(
+				&self,
+				storage: &mut sp_core::storage::Storage,
+			) -> Result<(), String> {
+				if self.value == 0 {
+					return Err("value can't be zero".into())
+				}
+				storage.top.insert(TOY_VALUE.to_vec(), (self.value + self.extra).encode());
+				Ok(())
+			}
+		}
+	}
+
+	#[test]
+	fn genesis_macro_builds_all_configs() {
+		use crate::mock::SystemConfig;
+		use genesis::*;
+
+		let mut ext = crate::genesis! { Test;
+			SystemConfig: { code: vec![1, 2, 3] },
+			ToyConfig: { value: 7 },
+		}
+		.build();
+		ext.execute_with(|| {
+			assert_eq!(
+				unhashed::get_raw(sp_core::storage::well_known_keys::CODE),
+				Some(vec![1, 2, 3])
+			);
+			assert_eq!(unhashed::get::<u32>(TOY_VALUE), Some(7));
+			assert!(crate::BlockHash::<Test>::contains_key(0));
+		});
+
+		let mut ext = crate::genesis! { Test; ToyConfig: { value: 1, extra: 2 } }.build();
+		ext.execute_with(|| {
+			assert_eq!(unhashed::get::<u32>(TOY_VALUE), Some(3));
+			// Only the listed configs are part of the state.
+			assert!(!crate::BlockHash::<Test>::contains_key(0));
+		});
+	}
+
+	#[test]
+	fn genesis_macro_names_failing_config() {
+		use crate::mock::SystemConfig;
+		use genesis::*;
+
+		let err = crate::genesis! { Test; SystemConfig: default, ToyConfig: default }
+			.try_build()
+			.map(|_| ())
+			.unwrap_err();
+		assert_eq!(err, "failed to build the genesis of `ToyConfig`: value can't be zero");
+
+		let err = MockExternalitiesBuilder::<Test>::new()
+			.with_genesis(ToyConfig::default())
+			.try_build()
+			.map(|_| ())
+			.unwrap_err();
+		assert!(err.contains("tests::genesis::ToyConfig"), "{}", err);
+	}
+
+	#[test]
+	fn trie_backend_records_realistic_proofs() {
+		let mut ext = MockExternalitiesBuilder::<Test>::new().with_trie_backend();
+		let map_key = |i: u32| {
+			[&storage_prefix(b"Toy", b"Map")[..], &i.using_encoded(sp_io::hashing::blake2_128)[..]]
+				.concat()
+		};
+		let child = ChildInfo::new_default(b"toy_child");
+		ext.execute_with(|| {
+			unhashed::put(b"small", &1u32);
+			(0..100).for_each(|i| unhashed::put(&map_key(i), &[i as u8; 32]));
+			sp_io::default_child_storage::set(child.storage_key(), b"key", &[0u8; 64]);
+		});
+
+		let read_small = || {
+			unhashed::get::<u32>(b"small");
+		};
+		let iterate_map = || {
+			let prefix = storage_prefix(b"Toy", b"Map");
+			let mut key = prefix.to_vec();
+			while let Some(next) = sp_io::storage::next_key(&key).filter(|k| k.starts_with(&prefix))
+			{
+				unhashed::get_raw(&next);
+				key = next;
+			}
+		};
+
+		let small = ext.recorded_proof_size(read_small);
+		let map = ext.recorded_proof_size(iterate_map);
+		assert!(small < 1_000, "{}", small);
+		assert!(map > 100 * 32, "{}", map);
+		assert!(map > 10 * small);
+		assert!(ext.recorded_trie_nodes(iterate_map) > ext.recorded_trie_nodes(read_small));
+
+		// Each measurement starts from scratch, and changes of a measured closure are committed.
+		assert_eq!(ext.recorded_proof_size(read_small), small);
+		assert_eq!(ext.recorded_trie_nodes(|| {}), 0);
+		ext.recorded_proof_size(|| unhashed::put(b"small", &[0u8; 100]));
+		assert!(ext.recorded_proof_size(read_small) > small + 100);
+
+		// Accesses to child tries are part of the proof.
+		let child_read = ext.recorded_proof_size(|| {
+			sp_io::default_child_storage::get(child.storage_key(), b"key");
+		});
+		assert!(child_read > 64, "{}", child_read);
+	}
+}
@@ -0,0 +1,74 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Compare an output against a golden file, re-blessed on request.
+use std::path::Path;
+
+/// The environment variable making [`assert_golden_file`] write the golden files instead of
+/// comparing against them.
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// Compare `actual` with the content of the golden file at `path`.
+///
+/// If the [`UPDATE_GOLDEN_ENV`] environment variable is set, the file is written with `actual`
+/// instead. Otherwise, a line diff between the file and `actual` is returned on mismatch.
+pub fn check_golden_file(path: impl AsRef<Path>, actual: &str) -> Result<(), String> {
+	let path = path.as_ref();
+	if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+		return std::fs::write(path, actual)
+			.map_err(|e| format!("failed to write golden file {}: {}", path.display(), e))
+	}
+
+	let expected = std::fs::read_to_string(path).map_err(|e| {
+		format!(
+			"failed to read golden file {}: {}; run with `{}=1` to create it",
+			path.display(),
+			e,
+			UPDATE_GOLDEN_ENV,
+		)
+	})?;
+	if expected == actual {
+		return Ok(())
+	}
+
+	let (expected, actual) =
+		(expected.lines().collect::<Vec<_>>(), actual.lines().collect::<Vec<_>>());
+	let mut diff = String::new();
+	for line in 0..expected.len().max(actual.len()) {
+		match (expected.get(line), actual.get(line)) {
+			(Some(e), Some(a)) if e == a => diff += &format!(" {}\n", e),
+			(e, a) => {
+				e.into_iter().for_each(|e| diff += &format!("-{}\n", e));
+				a.into_iter().for_each(|a| diff += &format!("+{}\n", a));
+			},
+		}
+	}
+	Err(format!(
+		"golden file {} differs, run with `{}=1` to update it if the change is intended:\n{}",
+		path.display(),
+		UPDATE_GOLDEN_ENV,
+		diff,
+	))
+}
+
+/// Assert that `actual` matches the golden file at `path`, see [`check_golden_file`].
+#[track_caller]
+pub fn assert_golden_file(path: impl AsRef<Path>, actual: &str) {
+	if let Err(e) = check_golden_file(path, actual) {
+		panic!("{}", e)
+	}
+}
@@ -0,0 +1,340 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Measure how the storage of a pallet grows with its inputs.
+
+use frame_support::{
+	storage::{storage_prefix, unhashed},
+	traits::PalletInfoAccess,
+};
+use sp_core::hexdisplay::HexDisplay;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The storage footprint of a group of keys.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct StorageFootprint {
+	/// The number of entries.
+	pub entries: u64,
+	/// The encoded size of all keys and values.
+	pub bytes: u64,
+}
+
+/// A sample of the storage taken by [`StorageGrowthMeter::checkpoint`].
+#[derive(Clone, Debug)]
+pub struct StorageCheckpoint {
+	/// The label of the checkpoint.
+	pub label: String,
+	/// The number of operations reported until the checkpoint.
+	pub ops: u64,
+	/// The footprint of each pallet, or of each unknown key prefix.
+	pub groups: BTreeMap<String, StorageFootprint>,
+}
+
+impl StorageCheckpoint {
+	/// The footprint of the whole sampled storage.
+	pub fn total(&self) -> StorageFootprint {
+		self.groups.values().fold(Default::default(), |total, group| StorageFootprint {
+			entries: total.entries + group.entries,
+			bytes: total.bytes + group.bytes,
+		})
+	}
+}
+
+/// Measures how the storage grows with the operations of a scenario.
+///
+/// Keys are grouped by pallet, using the names registered with [`Self::pallet`]; keys of
+/// unknown pallets are grouped by their 16 byte prefix and well-known keys under `well-known`.
+#[derive(Default)]
+pub struct StorageGrowthMeter {
+	pallets: BTreeMap<[u8; 16], String>,
+	excluded: Vec<Vec<u8>>,
+	ops: u64,
+	checkpoints: Vec<StorageCheckpoint>,
+}
+
+impl StorageGrowthMeter {
+	/// Create a new meter, without any checkpoint.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Name the storage of pallet `P` in the checkpoints.
+	pub fn pallet<P: PalletInfoAccess>(mut self) -> Self {
+		self.pallets
+			.insert(sp_io::hashing::twox_128(P::name().as_bytes()), P::name().into());
+		self
+	}
+
+	/// Don't sample the keys starting with `prefix`.
+	pub fn exclude(mut self, prefix: &[u8]) -> Self {
+		self.excluded.push(prefix.to_vec());
+		self
+	}
+
+	/// Don't sample the storage item `storage` of pallet `P`, e.g. `"Events"`.
+	pub fn exclude_storage<P: PalletInfoAccess>(self, storage: &str) -> Self {
+		self.exclude(&storage_prefix(P::name().as_bytes(), storage.as_bytes()))
+	}
+
+	/// Take the `start` checkpoint.
+	pub fn start(mut self) -> Self {
+		self.checkpoint("start");
+		self
+	}
+
+	/// Report that `ops` more operations were executed.
+	pub fn record_ops(&mut self, ops: u64) {
+		self.ops += ops;
+	}
+
+	/// Sample the storage, labeling the checkpoint `label`.
+	pub fn checkpoint(&mut self, label: impl Into<String>) {
+		let mut groups = BTreeMap::<String, StorageFootprint>::new();
+		let mut key = Vec::new();
+		while let Some(next) = sp_io::storage::next_key(&key) {
+			if let Some(excluded) = self.excluded.iter().find(|prefix| next.starts_with(prefix)) {
+				// Jump past all the keys of the excluded prefix.
+				match prefix_end(excluded) {
+					Some(end) if unhashed::exists(&end) => {
+						key = end;
+						self.sample(&mut groups, &key);
+					},
+					Some(end) => key = end,
+					None => break,
+				}
+				continue
+			}
+			self.sample(&mut groups, &next);
+			key = next;
+		}
+
+		self.checkpoints
+			.push(StorageCheckpoint { label: label.into(), ops: self.ops, groups });
+	}
+
+	fn sample(&self, groups: &mut BTreeMap<String, StorageFootprint>, key: &[u8]) {
+		if self.excluded.iter().any(|prefix| key.starts_with(prefix)) {
+			return
+		}
+		let group = match key.get(..16) {
+			_ if key.starts_with(b":") => "well-known".into(),
+			Some(prefix) => self
+				.pallets
+				.get(prefix)
+				.cloned()
+				.unwrap_or_else(|| format!("0x{}", HexDisplay::from(&prefix))),
+			None => format!("0x{}", HexDisplay::from(&key)),
+		};
+		let footprint = groups.entry(group).or_default();
+		footprint.entries += 1;
+		footprint.bytes +=
+			(key.len() + sp_io::storage::read(key, &mut [], 0).unwrap_or(0) as usize) as u64;
+	}
+
+	/// The checkpoints taken so far.
+	pub fn checkpoints(&self) -> &[StorageCheckpoint] {
+		&self.checkpoints
+	}
+
+	/// Assert that between any two consecutive checkpoints the storage grew by at most
+	/// `bytes_per_op` bytes and `entries_per_op` entries per reported operation.
+	///
+	/// # Panics
+	///
+	/// Naming the exceeded limits, with a table of the growth of each group, if the storage grew
+	/// faster.
+	#[track_caller]
+	pub fn assert_growth_at_most(&self, bytes_per_op: u64, entries_per_op: u64) {
+		for window in self.checkpoints.windows(2) {
+			let (from, to) = (&window[0], &window[1]);
+			let ops = to.ops - from.ops;
+			let (before, after) = (from.total(), to.total());
+			let bytes = after.bytes.saturating_sub(before.bytes);
+			let entries = after.entries.saturating_sub(before.entries);
+			let exceeded = [
+				(bytes > bytes_per_op.saturating_mul(ops))
+					.then(|| format!("{} bytes, more than {} bytes per op", bytes, bytes_per_op)),
+				(entries > entries_per_op.saturating_mul(ops)).then(|| {
+					format!("{} entries, more than {} entries per op", entries, entries_per_op)
+				}),
+			]
+			.into_iter()
+			.flatten()
+			.collect::<Vec<_>>();
+			if !exceeded.is_empty() {
+				panic!(
+					"storage grew by {} between `{}` and `{}` ({} ops):\n{}",
+					exceeded.join(" and "),
+					from.label,
+					to.label,
+					ops,
+					growth_table(from, to),
+				);
+			}
+		}
+	}
+}
+
+/// The first key after all the keys starting with `prefix`, if any.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+	let mut end = prefix.to_vec();
+	while let Some(last) = end.pop() {
+		if last < u8::MAX {
+			end.push(last + 1);
+			return Some(end)
+		}
+	}
+	None
+}
+
+fn growth_table(from: &StorageCheckpoint, to: &StorageCheckpoint) -> String {
+	let ops = (to.ops - from.ops).max(1);
+	let mut table = format!(
+		"{:<36} {:>10} {:>10} {:>12} {:>12} {:>12}\n",
+		"group", "entries", "+entries", "bytes", "+bytes", "bytes/op"
+	);
+	let groups = from.groups.keys().chain(to.groups.keys()).collect::<BTreeSet<_>>();
+	for group in groups {
+		let before = from.groups.get(group).copied().unwrap_or_default();
+		let after = to.groups.get(group).copied().unwrap_or_default();
+		let bytes = after.bytes as i64 - before.bytes as i64;
+		table += &format!(
+			"{:<36} {:>10} {:>+10} {:>12} {:>+12} {:>12}\n",
+			group,
+			after.entries,
+			after.entries as i64 - before.entries as i64,
+			after.bytes,
+			bytes,
+			bytes / ops as i64,
+		);
+	}
+	table
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, Test};
+	use codec::Encode;
+
+	mod growth {
+		use super::*;
+
+		pub fn items_prefix() -> [u8; 32] {
+			storage_prefix(b"Toy", b"Items")
+		}
+
+		/// A toy pallet adding one item per operation.
+		pub fn add_item(index: u32) {
+			unhashed::put(&[&items_prefix()[..], &index.encode()].concat(), &[0u8; 32]);
+		}
+
+		/// A toy pallet accidentally storing a full copy of all items on each operation.
+		pub fn add_item_with_snapshot(index: u32) {
+			add_item(index);
+			let snapshot = (0..=index).map(|_| [0u8; 32]).collect::<Vec<_>>();
+			let key = [&storage_prefix(b"Toy", b"Snapshots")[..], &index.encode()].concat();
+			unhashed::put(&key, &snapshot);
+		}
+
+		pub struct Toy;
+
+		impl PalletInfoAccess for Toy {
+			fn index() -> usize {
+				0
+			}
+			fn name() -> &'static str {
+				"Toy"
+			}
+			fn module_name() -> &'static str {
+				"toy"
+			}
+			fn crate_version() -> frame_support::traits::CrateVersion {
+				frame_support::traits::CrateVersion::new(0, 1, 0)
+			}
+		}
+
+		pub fn stress(add: fn(u32)) -> StorageGrowthMeter {
+			let mut meter = StorageGrowthMeter::new()
+				.pallet::<Toy>()
+				.pallet::<crate::Pallet<Test>>()
+				.exclude_storage::<crate::Pallet<Test>>("Events")
+				.start();
+			for round in 0..4 {
+				for index in round * 25..(round + 1) * 25 {
+					add(index);
+					crate::Pallet::<Test>::deposit_event(crate::Event::<Test>::Remarked {
+						sender: 1,
+						hash: Default::default(),
+					});
+				}
+				meter.record_ops(25);
+				meter.checkpoint(format!("after {} ops", (round + 1) * 25));
+			}
+			meter
+		}
+	}
+
+	#[test]
+	fn storage_growth_meter_accepts_linear_growth() {
+		use growth::*;
+
+		new_test_ext().execute_with(|| {
+			let meter = stress(add_item);
+			let checkpoints = meter.checkpoints();
+			assert_eq!(checkpoints.len(), 5);
+			assert_eq!(checkpoints[4].ops, 100);
+			assert_eq!(
+				checkpoints[4].groups["Toy"],
+				StorageFootprint { entries: 100, bytes: 100 * (32 + 4 + 32) }
+			);
+			// Events are excluded, only the event count and topics are seen.
+			assert!(checkpoints[4].groups["System"].bytes < 1_000);
+			meter.assert_growth_at_most(100, 1);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "storage grew by 33025 bytes, more than 1000 bytes per op between \
+		`after 25 ops` and `after 50 ops`")]
+	fn storage_growth_meter_catches_quadratic_growth() {
+		use growth::*;
+
+		new_test_ext().execute_with(|| {
+			let meter = stress(add_item_with_snapshot);
+			let table = growth_table(&meter.checkpoints()[3], &meter.checkpoints()[4]);
+			assert!(table.lines().any(|line| line.starts_with("Toy ")), "{}", table);
+			meter.assert_growth_at_most(1_000, 2);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "storage grew by 25 entries, more than 0 entries per op between \
+		`start` and `after 25 ops`")]
+	fn storage_growth_meter_names_the_exceeded_limit() {
+		use growth::*;
+
+		new_test_ext().execute_with(|| stress(add_item).assert_growth_at_most(100, 0));
+	}
+
+	#[test]
+	fn prefix_end_skips_all_prefixed_keys() {
+		assert_eq!(prefix_end(&[1, 2]), Some(vec![1, 3]));
+		assert_eq!(prefix_end(&[1, u8::MAX]), Some(vec![2]));
+		assert_eq!(prefix_end(&[u8::MAX]), None);
+	}
+}
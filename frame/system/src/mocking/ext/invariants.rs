@@ -0,0 +1,268 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Invariants checked at the boundaries of blocks, steps and dispatches.
+
+use super::with_extension;
+use sp_externalities::{Extension, ExternalitiesExt};
+use std::{any::Any, sync::Arc};
+
+/// A named invariant registered with [`register_invariant`].
+type Invariant = (String, Arc<dyn Fn() -> Result<(), String> + Send + Sync>);
+
+/// The invariants registered in the externalities, checked by the mocking drivers.
+///
+/// The drivers call [`check_invariants`] at their boundaries:
+/// [`TimeTravel`](crate::mocking::TimeTravel) after each block whose hooks are executed,
+/// [`MockDispatcher`](crate::mocking::MockDispatcher) after each dispatch,
+/// [`MockTaskRunner`](crate::mocking::MockTaskRunner) after each task,
+/// [`SteppedMigrationDriver`](crate::mocking::SteppedMigrationDriver) after each step,
+/// [`MigrationValidator`](crate::mocking::MigrationValidator) after the migration, and
+/// [`assert_deterministic`](crate::mocking::assert_deterministic) and
+/// [`randomize_iteration_order`](crate::mocking::randomize_iteration_order) after the scenario.
+#[derive(Default, Clone)]
+pub struct InvariantRegistry {
+	invariants: Vec<Invariant>,
+	suspended: u32,
+}
+
+impl Extension for InvariantRegistry {
+	fn as_mut_any(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+impl InvariantRegistry {
+	/// Access the registry of the current externalities, registering it if needed.
+	fn with<R>(f: impl FnOnce(&mut Self) -> R) -> R {
+		with_extension("invariants", f)
+	}
+
+	/// The invariants registered in the current externalities, if any, to check them in other
+	/// externalities as well.
+	pub(super) fn current() -> Option<Self> {
+		sp_externalities::with_externalities(|mut ext| {
+			ext.extension::<Self>()
+				.map(|registry| Self { invariants: registry.invariants.clone(), suspended: 0 })
+		})
+		.flatten()
+	}
+}
+
+/// Register the invariant `check`, checked by all the mocking drivers of the current
+/// externalities until they are dropped.
+///
+/// `check` returns the description of the violation, if any.
+pub fn register_invariant(
+	name: impl Into<String>,
+	check: impl Fn() -> Result<(), String> + Send + Sync + 'static,
+) {
+	InvariantRegistry::with(|registry| registry.invariants.push((name.into(), Arc::new(check))));
+}
+
+/// Check all the registered invariants, unless within [`with_invariants_suspended`].
+///
+/// # Panics
+///
+/// With the name of the first violated invariant, the `boundary` and the violation.
+#[track_caller]
+pub fn check_invariants(boundary: &str) {
+	let invariants = sp_externalities::with_externalities(|mut ext| {
+		ext.extension::<InvariantRegistry>()
+			.filter(|registry| registry.suspended == 0)
+			.map(|registry| registry.invariants.clone())
+	});
+	for (name, check) in invariants.flatten().unwrap_or_default() {
+		if let Err(e) = check() {
+			panic!("invariant `{}` violated {}: {}", name, boundary, e);
+		}
+	}
+}
+
+/// Execute `f` without checking the invariants, for intentionally inconsistent phases.
+///
+/// The checks resume once `f` returns, or panics.
+pub fn with_invariants_suspended<R>(f: impl FnOnce() -> R) -> R {
+	/// Resumes the checks when dropped, also while unwinding.
+	struct Resume;
+
+	impl Drop for Resume {
+		fn drop(&mut self) {
+			// Not `InvariantRegistry::with`, panicking while unwinding would abort.
+			sp_externalities::with_externalities(|mut ext| {
+				if let Some(registry) = ext.extension::<InvariantRegistry>() {
+					registry.suspended = registry.suspended.saturating_sub(1);
+				}
+			});
+		}
+	}
+
+	InvariantRegistry::with(|registry| registry.suspended += 1);
+	let _resume = Resume;
+	f()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		mock::{new_test_ext, Test},
+		mocking::{jump_to_block, MockDispatcher},
+	};
+	use frame_support::dispatch::{DispatchInfo, DispatchResultWithPostInfo, PostDispatchInfo};
+	use sp_runtime::traits::Dispatchable;
+
+	mod invariants {
+		use super::*;
+		use crate::mock::RuntimeOrigin;
+		use frame_support::{storage::types::ValueQuery, Twox64Concat};
+
+		#[frame_support::storage_alias]
+		pub type Balances = StorageMap<Toy, Twox64Concat, u64, u64>;
+
+		#[frame_support::storage_alias]
+		pub type TotalIssuance = StorageValue<Toy, u64, ValueQuery>;
+
+		/// A toy dispatchable minting to an account, forgetting the issuance if `buggy`.
+		pub struct Mint {
+			pub who: u64,
+			pub amount: u64,
+			pub buggy: bool,
+		}
+
+		impl Dispatchable for Mint {
+			type RuntimeOrigin = RuntimeOrigin;
+			type Config = Test;
+			type Info = DispatchInfo;
+			type PostInfo = PostDispatchInfo;
+
+			fn dispatch(self, _: RuntimeOrigin) -> DispatchResultWithPostInfo {
+				Balances::mutate(self.who, |balance| {
+					*balance = Some(balance.unwrap_or(0) + self.amount)
+				});
+				if !self.buggy {
+					TotalIssuance::mutate(|issuance| *issuance += self.amount);
+				}
+				Ok(().into())
+			}
+		}
+
+		pub fn register() {
+			register_invariant("total issuance", || {
+				let sum = Balances::iter_values().sum::<u64>();
+				let issuance = TotalIssuance::get();
+				(sum == issuance).then_some(()).ok_or_else(|| {
+					format!("balances sum to {} but the issuance is {}", sum, issuance)
+				})
+			});
+			register_invariant("no dust", || {
+				match Balances::iter().find(|(_, balance)| *balance < 10) {
+					Some((who, balance)) => Err(format!("{} only has {}", who, balance)),
+					None => Ok(()),
+				}
+			});
+		}
+
+		pub fn mint(who: u64, amount: u64, buggy: bool) -> Mint {
+			Mint { who, amount, buggy }
+		}
+	}
+
+	#[test]
+	#[should_panic(
+		expected = "invariant `total issuance` violated after dispatch 1: balances sum \
+		to 150 but the issuance is 100"
+	)]
+	fn violated_invariant_is_reported_with_the_boundary() {
+		use crate::mock::RuntimeOrigin;
+		use invariants::*;
+
+		new_test_ext().execute_with(|| {
+			register();
+			let dispatcher = MockDispatcher::<Test>::default();
+			dispatcher.dispatch(mint(1, 100, false), RuntimeOrigin::root()).unwrap();
+			let _ = dispatcher.dispatch(mint(2, 50, true), RuntimeOrigin::root());
+		});
+	}
+
+	#[test]
+	fn invariants_are_checked_by_the_drivers() {
+		use invariants::*;
+
+		new_test_ext().execute_with(|| {
+			register();
+			// No invariant is checked outside of the drivers.
+			Balances::insert(1, 5);
+			let error = std::panic::catch_unwind(|| jump_to_block::<Test, ()>(5)).unwrap_err();
+			assert_eq!(
+				error.downcast_ref::<String>().map(String::as_str),
+				Some("invariant `total issuance` violated after block 5: balances sum to 5 but the issuance is 0")
+			);
+
+			TotalIssuance::put(5);
+			let error = std::panic::catch_unwind(|| check_invariants("now")).unwrap_err();
+			assert_eq!(
+				error.downcast_ref::<String>().map(String::as_str),
+				Some("invariant `no dust` violated now: 1 only has 5")
+			);
+		});
+	}
+
+	#[test]
+	fn invariants_can_be_suspended() {
+		use crate::mock::RuntimeOrigin;
+		use invariants::*;
+
+		new_test_ext().execute_with(|| {
+			register();
+			let dispatcher = MockDispatcher::<Test>::default();
+			let result = with_invariants_suspended(|| {
+				let result = dispatcher.dispatch(mint(1, 100, true), RuntimeOrigin::root());
+				TotalIssuance::put(100);
+				result
+			});
+			assert!(result.is_ok());
+			check_invariants("after the suspension");
+			dispatcher.dispatch(mint(1, 100, false), RuntimeOrigin::root()).unwrap();
+		});
+
+		// Without a registered invariant, there is nothing to check.
+		new_test_ext().execute_with(|| check_invariants("without invariants"));
+	}
+
+	#[test]
+	fn invariants_resume_after_a_panicking_suspension() {
+		use invariants::*;
+
+		new_test_ext().execute_with(|| {
+			register();
+			let result = std::panic::catch_unwind(|| {
+				with_invariants_suspended(|| {
+					Balances::insert(1, 5);
+					panic!("failed while suspended");
+				})
+			});
+			assert!(result.is_err());
+
+			let error = std::panic::catch_unwind(|| check_invariants("now")).unwrap_err();
+			assert_eq!(
+				error.downcast_ref::<String>().map(String::as_str),
+				Some("invariant `total issuance` violated now: balances sum to 5 but the issuance is 0")
+			);
+		});
+	}
+}
@@ -0,0 +1,382 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detect the scenarios depending on the iteration order of the storage.
+
+use super::{
+	check_invariants, events_prefixes, first_difference, storage_pairs, MockExternalitiesBuilder,
+};
+use crate::Config;
+use codec::{Decode, Encode};
+use frame_support::storage::unhashed;
+use sp_core::hexdisplay::HexDisplay;
+use sp_runtime::traits::{One, TrailingZeroInput};
+use std::{
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet},
+	fmt,
+};
+
+/// The number of runs of [`randomize_iteration_order`].
+const ITERATION_ORDER_RUNS: u64 = 8;
+
+/// The randomization of one run of [`randomize_iteration_order`].
+///
+/// The scenario uses [`Self::label`] as the stand-in of each logical entry, e.g. the account of
+/// the `n`th user, so the keys of the entries and therefore the iteration order change with
+/// every run.
+pub struct IterationOrder {
+	seed: u64,
+	labels: RefCell<BTreeMap<Vec<u8>, u32>>,
+	draws: RefCell<u64>,
+}
+
+impl IterationOrder {
+	fn new(seed: u64) -> Self {
+		Self { seed, labels: Default::default(), draws: Default::default() }
+	}
+
+	fn entropy(&self, subject: &[u8], index: u64) -> [u8; 32] {
+		(b"iteration_order", self.seed, subject, index).using_encoded(sp_io::hashing::blake2_256)
+	}
+
+	fn draw(&self) -> [u8; 32] {
+		let mut draws = self.draws.borrow_mut();
+		*draws += 1;
+		self.entropy(b"draw", *draws)
+	}
+
+	/// The seed of this run.
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
+	/// The stand-in of the logical entry `index` in this run.
+	pub fn label<L: Encode + Decode>(&self, index: u32) -> L {
+		let entropy = self.entropy(b"label", index.into());
+		let label = L::decode(&mut TrailingZeroInput::new(&entropy[..]))
+			.expect("infinite length input; no invalid inputs for type; qed");
+		self.labels.borrow_mut().insert(label.encode(), index);
+		label
+	}
+
+	/// The index of the logical entry `label` stands in for.
+	pub fn index_of<L: Encode>(&self, label: &L) -> Option<u32> {
+		self.labels.borrow().get(&label.encode()).copied()
+	}
+
+	/// Shuffle `items`, e.g. to insert the entries in a different order in every run.
+	pub fn shuffle<I>(&self, items: &mut [I]) {
+		for i in (1..items.len()).rev() {
+			let draw = u64::from_le_bytes(self.draw()[..8].try_into().expect("8 bytes; qed"));
+			items.swap(i, (draw % (i as u64 + 1)) as usize);
+		}
+	}
+
+	/// Replace the labels, and the common hashes of them used in storage keys, in `bytes` by
+	/// their index.
+	fn normalize(&self, bytes: &[u8]) -> Vec<u8> {
+		use sp_io::hashing::{blake2_128, blake2_256, twox_128, twox_64};
+
+		let mut bytes = bytes.to_vec();
+		for (label, index) in self.labels.borrow().iter() {
+			let patterns: [Vec<u8>; 5] = [
+				blake2_256(label).to_vec(),
+				blake2_128(label).to_vec(),
+				twox_128(label).to_vec(),
+				twox_64(label).to_vec(),
+				label.clone(),
+			];
+			for (kind, pattern) in patterns.iter().enumerate() {
+				let marker = (b"label", kind as u8, index).encode();
+				bytes = replace_all(&bytes, pattern, &marker);
+			}
+		}
+		bytes
+	}
+}
+
+fn replace_all(bytes: &[u8], pattern: &[u8], with: &[u8]) -> Vec<u8> {
+	if pattern.is_empty() {
+		return bytes.to_vec()
+	}
+	let mut result = Vec::with_capacity(bytes.len());
+	let mut rest = bytes;
+	while !rest.is_empty() {
+		if rest.starts_with(pattern) {
+			result.extend_from_slice(with);
+			rest = &rest[pattern.len()..];
+		} else {
+			result.push(rest[0]);
+			rest = &rest[1..];
+		}
+	}
+	result
+}
+
+/// What a run of [`randomize_iteration_order`] is compared on.
+struct Observables<R> {
+	returned: R,
+	events: Vec<Vec<u8>>,
+	storage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Run `scenario` several times, each time with differently labeled entries, and assert that the
+/// outcome doesn't depend on the storage iteration order.
+///
+/// Every run starts from a fresh genesis at block 1, into which irrelevant entries are
+/// interleaved at random positions under the prefix of each pallet in genesis and under each of
+/// the `perturbed` prefixes, e.g. `twox_128(b"Toy")` for a pallet only written by the scenario
+/// or the prefix of a storage map. The runs must agree on:
+/// - the value returned by `scenario`, which should refer to entries by their index, see
+///   [`IterationOrder::index_of`];
+/// - the deposited events, regardless of their order;
+/// - the final storage, except for the events.
+///
+/// Labels and their hashes are replaced by their index in the events and storage before the
+/// comparison. The runs are derived from `seed`, so a failure can be reproduced.
+///
+/// # Panics
+///
+/// Naming the first observable that differs and the seeds of the two runs.
+pub fn randomize_iteration_order<T: Config, R: PartialEq + fmt::Debug>(
+	seed: u64,
+	perturbed: &[&[u8]],
+	scenario: impl Fn(&IterationOrder) -> R,
+) {
+	// Appended to the noise keys to tell them apart from the entries of the scenario.
+	let noise_marker = sp_io::hashing::twox_128(b":iteration_order_noise:");
+	let events_prefixes = events_prefixes::<T>();
+	let run = |run| {
+		let order = IterationOrder::new(
+			(seed, run).using_encoded(|seed| u64::from_le_bytes(sp_io::hashing::twox_64(seed))),
+		);
+		MockExternalitiesBuilder::<T>::new().build().execute_with(|| {
+			crate::Pallet::<T>::set_block_number(One::one());
+			let prefixes = storage_pairs()
+				.into_iter()
+				.filter_map(|(key, _)| key.get(..16).map(<[u8]>::to_vec))
+				.chain(perturbed.iter().map(|prefix| prefix.to_vec()))
+				.collect::<BTreeSet<_>>();
+			for prefix in prefixes {
+				for _ in 0..4 {
+					let noise = order.draw();
+					unhashed::put_raw(&[&prefix, &noise[..16], &noise_marker[..]].concat(), &noise);
+				}
+			}
+
+			let returned = scenario(&order);
+			check_invariants(&format!("after the scenario with seed {}", order.seed()));
+			let mut events = crate::Pallet::<T>::read_events_no_consensus()
+				.map(|record| order.normalize(&record.event.encode()))
+				.collect::<Vec<_>>();
+			events.sort();
+			let mut storage = storage_pairs()
+				.into_iter()
+				.filter(|(key, _)| !key.ends_with(&noise_marker))
+				.filter(|(key, _)| !events_prefixes.iter().any(|prefix| key.starts_with(prefix)))
+				.map(|(key, value)| (order.normalize(&key), order.normalize(&value)))
+				.collect::<Vec<_>>();
+			storage.sort();
+			(order.seed(), Observables { returned, events, storage })
+		})
+	};
+
+	let (first_seed, first) = run(0);
+	for i in 1..ITERATION_ORDER_RUNS {
+		let (seed, other) = run(i);
+		let diverged = |observable: &str, a: &dyn fmt::Debug, b: &dyn fmt::Debug| -> ! {
+			panic!(
+				"iteration order changed the {}: with seed {}: {:?}, with seed {}: {:?}",
+				observable, first_seed, a, seed, b,
+			)
+		};
+		if first.returned != other.returned {
+			diverged("returned value", &first.returned, &other.returned);
+		}
+		if first.events != other.events {
+			let (a, b) = first_difference(&first.events, &other.events);
+			diverged("events", &a.map(HexDisplay::from), &b.map(HexDisplay::from));
+		}
+		if first.storage != other.storage {
+			let (a, b) = first_difference(&first.storage, &other.storage);
+			let hex = |pair: Option<&(Vec<u8>, Vec<u8>)>| {
+				pair.map(|(k, v)| format!("0x{} => 0x{}", HexDisplay::from(k), HexDisplay::from(v)))
+			};
+			diverged("storage", &hex(a), &hex(b));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::Test;
+	use frame_support::storage::storage_prefix;
+
+	mod iteration {
+		use super::*;
+
+		pub fn toy_prefix() -> [u8; 16] {
+			sp_io::hashing::twox_128(b"Toy")
+		}
+
+		pub fn deposits_prefix() -> [u8; 32] {
+			storage_prefix(b"Toy", b"Deposits")
+		}
+
+		/// A toy pallet storing a deposit per account.
+		pub fn deposit(who: u64, amount: u64) {
+			let key = [
+				&deposits_prefix()[..],
+				&who.using_encoded(sp_io::hashing::blake2_128)[..],
+				&who.encode(),
+			]
+			.concat();
+			unhashed::put(&key, &amount);
+		}
+
+		pub fn deposits() -> Vec<(u64, u64)> {
+			let prefix = deposits_prefix();
+			let mut deposits = Vec::new();
+			let mut key = prefix.to_vec();
+			while let Some(next) = sp_io::storage::next_key(&key).filter(|k| k.starts_with(&prefix))
+			{
+				let who = u64::decode(&mut &next[48..]).unwrap();
+				deposits.push((who, unhashed::get(&next).unwrap()));
+				key = next;
+			}
+			deposits
+		}
+
+		pub fn setup(order: &IterationOrder) {
+			let mut entries = (0..5u32).collect::<Vec<_>>();
+			order.shuffle(&mut entries);
+			for index in entries {
+				deposit(order.label(index), 10 * (index as u64 + 1));
+			}
+		}
+
+		/// Pays out all deposits, emitting an event per deposit.
+		pub fn payout_all() -> u64 {
+			deposits()
+				.into_iter()
+				.map(|(who, amount)| {
+					crate::Pallet::<Test>::deposit_event(crate::Event::<Test>::Remarked {
+						sender: who,
+						hash: sp_core::H256::repeat_byte(amount as u8),
+					});
+					amount
+				})
+				.sum()
+		}
+	}
+
+	#[test]
+	fn iteration_order_independent_scenario_passes() {
+		use iteration::*;
+
+		randomize_iteration_order::<Test, _>(42, &[&toy_prefix()], |order| {
+			setup(order);
+			let total = payout_all();
+			assert_eq!(order.index_of(&order.label::<u64>(3)), Some(3));
+			total
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "iteration order changed the returned value: with seed")]
+	fn iteration_order_dependent_scenario_is_caught() {
+		use iteration::*;
+
+		randomize_iteration_order::<Test, _>(42, &[&toy_prefix()], |order| {
+			setup(order);
+			// Picks the "first" depositor as winner, which depends on the hash of its account.
+			let (winner, _) = deposits()[0];
+			order.index_of(&winner)
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "iteration order changed the storage: with seed")]
+	fn iteration_order_dependent_storage_is_caught() {
+		use iteration::*;
+
+		randomize_iteration_order::<Test, _>(7, &[&toy_prefix()], |order| {
+			setup(order);
+			let (winner, amount) = deposits()[0];
+			unhashed::put(b"winner", &(winner, amount));
+		});
+	}
+
+	#[test]
+	fn iteration_order_ignores_the_events_under_the_system_pallet_name() {
+		use super::super::dispatcher::tests::renamed_system::{System, Test};
+		use iteration::*;
+
+		randomize_iteration_order::<Test, _>(42, &[&toy_prefix()], |order| {
+			setup(order);
+			// The events are deposited in iteration order, only their storage differs.
+			for (who, amount) in deposits() {
+				System::deposit_event(crate::Event::<Test>::Remarked {
+					sender: who,
+					hash: sp_core::H256::repeat_byte(amount as u8),
+				});
+			}
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "iteration order changed the returned value: with seed")]
+	fn iteration_order_perturbs_the_given_prefixes() {
+		use iteration::*;
+
+		randomize_iteration_order::<Test, _>(42, &[&toy_prefix()], |_| {
+			unhashed::put(&storage_prefix(b"Toy", b"Value"), &1u32);
+			// The position of `Value` among the keys of `Toy`, fixed without the noise.
+			let mut key = toy_prefix().to_vec();
+			let mut position = 0;
+			while let Some(next) =
+				sp_io::storage::next_key(&key).filter(|k| k.starts_with(&toy_prefix()))
+			{
+				if next == storage_prefix(b"Toy", b"Value") {
+					return position
+				}
+				position += 1;
+				key = next;
+			}
+			unreachable!("`Value` was written")
+		});
+	}
+
+	#[test]
+	fn iteration_order_does_not_perturb_other_prefixes() {
+		use iteration::*;
+
+		randomize_iteration_order::<Test, _>(42, &[], |_| {
+			unhashed::put(&storage_prefix(b"Toy", b"Value"), &1u32);
+			sp_io::storage::next_key(&toy_prefix())
+		});
+	}
+
+	#[test]
+	fn replace_all_replaces_every_occurrence() {
+		assert_eq!(replace_all(b"abcabxab", b"ab", b"Z"), b"ZcZxZ".to_vec());
+		assert_eq!(replace_all(b"aaa", b"", b"Z"), b"aaa".to_vec());
+		assert_eq!(first_difference(&[1, 2, 3], &[1, 2]), (Some(&3), None),);
+	}
+}
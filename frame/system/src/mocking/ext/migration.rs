@@ -0,0 +1,628 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Validate a storage migration against the state exported by a previous version of the runtime.
+
+use super::{check_invariants, storage_pairs, InvariantRegistry};
+use crate::{pallet_prelude::BlockNumberFor, Config};
+use codec::{Decode, Encode};
+use frame_support::{
+	storage::unhashed,
+	traits::{Get, OnRuntimeUpgrade, PalletInfoAccess, StorageVersion},
+	weights::Weight,
+};
+use std::{fmt, marker::PhantomData, path::Path};
+
+/// A storage snapshot written by a previous version of the runtime, as exported by
+/// [`OldStateRecorder`] and consumed by [`MigrationValidator`].
+#[derive(Encode, Decode, Clone, PartialEq, Eq, Debug)]
+pub struct StateFixture {
+	/// The `spec_version` of the runtime that produced the state.
+	pub spec_version: u32,
+	/// The on-chain storage version of every recorded pallet, `None` if it was never set.
+	pub storage_versions: Vec<(String, Option<StorageVersion>)>,
+	/// All key/value pairs of the main trie.
+	pub storage: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl StateFixture {
+	/// Write the fixture SCALE encoded to `path`.
+	pub fn export(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+		std::fs::write(path, self.encode())
+	}
+
+	/// Read a fixture previously written by [`Self::export`].
+	pub fn load(path: impl AsRef<Path>) -> Result<Self, MigrationValidatorError> {
+		let bytes = std::fs::read(path).map_err(MigrationValidatorError::Io)?;
+		Self::decode(&mut &bytes[..]).map_err(MigrationValidatorError::Decode)
+	}
+
+	/// The recorded storage version of `pallet`.
+	///
+	/// Returns `None` if the pallet wasn't recorded at all and `Some(None)` if it was recorded
+	/// without a storage version being set.
+	pub fn storage_version(&self, pallet: &str) -> Option<Option<StorageVersion>> {
+		self.storage_versions.iter().find(|(name, _)| name == pallet).map(|(_, v)| *v)
+	}
+}
+
+/// Reads the on-chain storage version of a pallet, `None` if it isn't set.
+type ReadStorageVersion = fn() -> Option<StorageVersion>;
+
+/// Records the state written by the *previous* release of a pallet.
+///
+/// Compile it into a test of the old pallet version (e.g. pulled in as a renamed
+/// dev-dependency), run a setup scenario and export the result with [`StateFixture::export`].
+/// The current code then loads it with [`MigrationValidator::from_fixture`], so migrations are
+/// tested against what the old code actually wrote instead of hand-crafted "old" storage.
+pub struct OldStateRecorder<T> {
+	pallets: Vec<(&'static str, ReadStorageVersion)>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config> Default for OldStateRecorder<T> {
+	fn default() -> Self {
+		Self { pallets: Vec::new(), _phantom: Default::default() }
+	}
+}
+
+impl<T: Config> OldStateRecorder<T> {
+	/// Create a recorder which doesn't record any storage version yet.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Also record the on-chain storage version of pallet `P`.
+	pub fn pallet<P: PalletInfoAccess>(mut self) -> Self {
+		fn version<P: PalletInfoAccess>() -> Option<StorageVersion> {
+			unhashed::exists(&StorageVersion::storage_key::<P>()).then(StorageVersion::get::<P>)
+		}
+
+		self.pallets.push((P::name(), version::<P>));
+		self
+	}
+
+	/// Run `scenario` in `ext` and snapshot the resulting state.
+	pub fn record(
+		&self,
+		ext: &mut sp_io::TestExternalities,
+		scenario: impl FnOnce(),
+	) -> StateFixture {
+		ext.execute_with(|| {
+			scenario();
+
+			StateFixture {
+				spec_version: T::Version::get().spec_version,
+				storage_versions: self
+					.pallets
+					.iter()
+					.map(|(name, version)| (name.to_string(), version()))
+					.collect(),
+				storage: storage_pairs(),
+			}
+		})
+	}
+}
+
+/// Errors returned by [`MigrationValidator`].
+#[derive(Debug)]
+pub enum MigrationValidatorError {
+	/// The fixture could not be read.
+	Io(std::io::Error),
+	/// The fixture could not be decoded.
+	Decode(codec::Error),
+	/// The fixture was recorded by a runtime that is not older than the current one.
+	NotAnUpgrade {
+		/// `spec_version` stored in the fixture.
+		recorded: u32,
+		/// `spec_version` of the current runtime.
+		current: u32,
+	},
+	/// The storage version of a pallet in the fixture isn't the one the migration starts from.
+	StorageVersionMismatch {
+		/// Name of the pallet.
+		pallet: String,
+		/// Version recorded in the fixture, `None` if it wasn't set.
+		recorded: Option<StorageVersion>,
+		/// Version the migration expects to start from.
+		expected: StorageVersion,
+	},
+	/// The pallet was never recorded by the [`OldStateRecorder`].
+	PalletNotRecorded(String),
+	/// The migration or its `try-runtime` checks, including `try_state`, failed.
+	Migration(String),
+	/// A user assertion failed after the migration.
+	Assertion(String),
+}
+
+impl fmt::Display for MigrationValidatorError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "failed to read fixture: {}", e),
+			Self::Decode(e) => write!(f, "failed to decode fixture: {}", e),
+			Self::NotAnUpgrade { recorded, current } => write!(
+				f,
+				"fixture was recorded with spec_version {} which is not older than the current {}",
+				recorded, current,
+			),
+			Self::StorageVersionMismatch { pallet, recorded, expected } => write!(
+				f,
+				"pallet `{}` was recorded at storage version {:?}, but the migration expects to \
+				 start from {:?}",
+				pallet, recorded, expected,
+			),
+			Self::PalletNotRecorded(pallet) => write!(
+				f,
+				"pallet `{}` is not part of the fixture, record it with `OldStateRecorder::pallet`",
+				pallet,
+			),
+			Self::Migration(e) => write!(f, "migration failed: {}", e),
+			Self::Assertion(e) => write!(f, "post-migration assertion failed: {}", e),
+		}
+	}
+}
+
+/// The pallets whose `try_state` hooks [`MigrationValidator`] runs after the upgrade, usually
+/// the `AllPalletsWithSystem` of the runtime.
+///
+/// Implemented for every `frame_support::traits::TryState` with the `try-runtime` feature, and for
+/// every type, doing nothing, without it.
+pub trait MigrationTryState<BlockNumber> {
+	/// Run the `try_state` hooks of all pallets at `now`.
+	fn try_state_all(now: BlockNumber) -> Result<(), sp_runtime::TryRuntimeError>;
+}
+
+#[cfg(feature = "try-runtime")]
+impl<BlockNumber, Pallets: frame_support::traits::TryState<BlockNumber>>
+	MigrationTryState<BlockNumber> for Pallets
+{
+	fn try_state_all(now: BlockNumber) -> Result<(), sp_runtime::TryRuntimeError> {
+		Pallets::try_state(now, frame_support::traits::TryStateSelect::All)
+	}
+}
+
+#[cfg(not(feature = "try-runtime"))]
+impl<BlockNumber, Pallets> MigrationTryState<BlockNumber> for Pallets {
+	fn try_state_all(_: BlockNumber) -> Result<(), sp_runtime::TryRuntimeError> {
+		Ok(())
+	}
+}
+
+/// Runs a migration against a [`StateFixture`] recorded by the previous release.
+///
+/// `AllPalletsWithSystem` are the pallets whose `on_runtime_upgrade` and `try_state` hooks are
+/// run, only the System pallet by default.
+pub struct MigrationValidator<T, AllPalletsWithSystem = crate::Pallet<T>> {
+	fixture: StateFixture,
+	expected_versions: Vec<(&'static str, StorageVersion)>,
+	_phantom: PhantomData<(T, AllPalletsWithSystem)>,
+}
+
+impl<T, AllPalletsWithSystem> MigrationValidator<T, AllPalletsWithSystem>
+where
+	T: Config,
+	AllPalletsWithSystem: OnRuntimeUpgrade + MigrationTryState<BlockNumberFor<T>>,
+{
+	/// Load the fixture stored at `path`.
+	pub fn from_fixture(path: impl AsRef<Path>) -> Result<Self, MigrationValidatorError> {
+		StateFixture::load(path).map(Self::new)
+	}
+
+	/// Use an already loaded fixture.
+	pub fn new(fixture: StateFixture) -> Self {
+		Self { fixture, expected_versions: Vec::new(), _phantom: Default::default() }
+	}
+
+	/// Require pallet `P` to be recorded at `version`, the version the migration starts from.
+	pub fn expect_storage_version<P: PalletInfoAccess>(mut self, version: StorageVersion) -> Self {
+		self.expected_versions.push((P::name(), version));
+		self
+	}
+
+	/// Load the fixture into fresh externalities, run `Migration` and the `on_runtime_upgrade`
+	/// hooks of `AllPalletsWithSystem` with [`simulate_runtime_upgrade`] and then `assertions`.
+	///
+	/// With the `try-runtime` feature enabled, the `pre_upgrade`/`post_upgrade` checks of the
+	/// migrations and the `try_state` hooks of `AllPalletsWithSystem` are run as well, the latter
+	/// before `assertions`. The invariants registered in the calling externalities are checked
+	/// after the migration, see [`InvariantRegistry`]. The externalities are returned for further
+	/// inspection.
+	pub fn execute<Migration: OnRuntimeUpgrade>(
+		self,
+		assertions: impl FnOnce() -> Result<(), String>,
+	) -> Result<sp_io::TestExternalities, MigrationValidatorError> {
+		let current = T::Version::get().spec_version;
+		if self.fixture.spec_version >= current {
+			return Err(MigrationValidatorError::NotAnUpgrade {
+				recorded: self.fixture.spec_version,
+				current,
+			})
+		}
+
+		for (pallet, expected) in &self.expected_versions {
+			match self.fixture.storage_version(pallet) {
+				None => return Err(MigrationValidatorError::PalletNotRecorded(pallet.to_string())),
+				Some(recorded) if recorded.unwrap_or_default() != *expected =>
+					return Err(MigrationValidatorError::StorageVersionMismatch {
+						pallet: pallet.to_string(),
+						recorded,
+						expected: *expected,
+					}),
+				Some(_) => (),
+			}
+		}
+
+		let mut ext = sp_io::TestExternalities::new(Default::default());
+		if let Some(invariants) = InvariantRegistry::current() {
+			ext.register_extension(invariants);
+		}
+		ext.execute_with(|| {
+			for (key, value) in &self.fixture.storage {
+				unhashed::put_raw(key, value);
+			}
+
+			let last = crate::LastRuntimeUpgrade::<T>::get();
+			simulate_runtime_upgrade::<T, (Migration, AllPalletsWithSystem)>()
+				.map_err(|e| MigrationValidatorError::Migration(format!("{:?}", e)))?
+				.ok_or(MigrationValidatorError::NotAnUpgrade {
+					recorded: last.map_or(self.fixture.spec_version, |last| last.spec_version.0),
+					current,
+				})?;
+
+			AllPalletsWithSystem::try_state_all(crate::Pallet::<T>::block_number())
+				.map_err(|e| MigrationValidatorError::Migration(format!("{:?}", e)))?;
+			check_invariants("after the migration");
+
+			assertions().map_err(MigrationValidatorError::Assertion)
+		})?;
+
+		Ok(ext)
+	}
+}
+
+/// Run `Migrations` the way `frame_executive::Executive` does at the first block of a new runtime
+/// version, usually `(CustomMigrations, AllPalletsWithSystem)`.
+///
+/// The upgrade only happens if the `spec_version` or `spec_name` of `T::Version` changed since
+/// the [`LastRuntimeUpgrade`](crate::LastRuntimeUpgrade), which is then updated, and returns the
+/// weight of the migrations. Returns `Ok(None)` without running them otherwise.
+///
+/// With the `try-runtime` feature enabled, the `pre_upgrade`/`post_upgrade` checks of
+/// `Migrations` are run as well.
+pub fn simulate_runtime_upgrade<T: Config, Migrations: OnRuntimeUpgrade>(
+) -> Result<Option<Weight>, sp_runtime::TryRuntimeError> {
+	let current = T::Version::get();
+	let last = crate::LastRuntimeUpgrade::<T>::get();
+	if !last.map(|last| last.was_upgraded(&current)).unwrap_or(true) {
+		return Ok(None)
+	}
+	crate::LastRuntimeUpgrade::<T>::put(crate::LastRuntimeUpgradeInfo::from(current));
+
+	#[cfg(feature = "try-runtime")]
+	return Migrations::try_on_runtime_upgrade(true).map(Some);
+	#[cfg(not(feature = "try-runtime"))]
+	Ok(Some(Migrations::on_runtime_upgrade()))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use frame_support::traits::GetStorageVersion;
+
+	mod migration {
+		use super::*;
+		use crate as frame_system;
+		use frame_support::{derive_impl, parameter_types};
+		use sp_runtime::BuildStorage;
+		use sp_version::RuntimeVersion;
+
+		// The vendored previous release of the toy pallet, storing its values as `u32`.
+		#[frame_support::pallet]
+		pub mod toy_v0 {
+			use super::frame_system;
+			use frame_support::pallet_prelude::*;
+			use frame_system::pallet_prelude::*;
+
+			#[pallet::pallet]
+			pub struct Pallet<T>(_);
+
+			#[pallet::config]
+			pub trait Config: frame_system::Config {}
+
+			#[pallet::storage]
+			pub type Values<T> = StorageMap<_, Twox64Concat, u32, u32>;
+
+			#[pallet::call]
+			impl<T: Config> Pallet<T> {
+				#[pallet::call_index(0)]
+				#[pallet::weight(Weight::zero())]
+				pub fn store(_: OriginFor<T>, index: u32, value: u32) -> DispatchResult {
+					Values::<T>::insert(index, value);
+					Ok(())
+				}
+			}
+		}
+
+		// The current release of the toy pallet, storing `u64` values at storage version 1.
+		#[frame_support::pallet]
+		pub mod toy_v1 {
+			use super::frame_system;
+			use frame_support::{pallet_prelude::*, traits::OnRuntimeUpgrade};
+			use frame_system::pallet_prelude::*;
+
+			const STORAGE_VERSION: StorageVersion = StorageVersion::new(1);
+
+			#[pallet::pallet]
+			#[pallet::storage_version(STORAGE_VERSION)]
+			pub struct Pallet<T>(_);
+
+			#[pallet::config]
+			pub trait Config: frame_system::Config {}
+
+			#[pallet::storage]
+			pub type Values<T> = StorageMap<_, Twox64Concat, u32, u64>;
+
+			#[pallet::call]
+			impl<T: Config> Pallet<T> {
+				#[pallet::call_index(0)]
+				#[pallet::weight(Weight::zero())]
+				pub fn store(_: OriginFor<T>, index: u32, value: u64) -> DispatchResult {
+					Values::<T>::insert(index, value);
+					Ok(())
+				}
+			}
+
+			/// Migrates the first `ENTRIES` values to `u64`, forgetting the others.
+			pub struct MigrateToV1<T, const ENTRIES: u32>(PhantomData<T>);
+
+			impl<T: Config, const ENTRIES: u32> OnRuntimeUpgrade for MigrateToV1<T, ENTRIES> {
+				fn on_runtime_upgrade() -> Weight {
+					if Pallet::<T>::on_chain_storage_version() == 0 {
+						for index in 0..ENTRIES {
+							let key = Values::<T>::hashed_key_for(index);
+							if let Some(value) = frame_support::storage::unhashed::get::<u32>(&key)
+							{
+								Values::<T>::insert(index, value as u64);
+							}
+						}
+						STORAGE_VERSION.put::<Pallet<T>>();
+					}
+					Weight::zero()
+				}
+			}
+		}
+
+		/// Declares a runtime with the System pallet, `toy` as `Toy` and `spec_version`.
+		macro_rules! toy_runtime {
+			($toy:ident, $spec_version:expr) => {
+				type Block = frame_system::mocking::MockBlock<Runtime>;
+
+				frame_support::construct_runtime!(
+					pub enum Runtime
+					{
+						System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+						Toy: $toy,
+					}
+				);
+
+				parameter_types! {
+					pub Version: RuntimeVersion = RuntimeVersion {
+						spec_name: "toy".into(),
+						spec_version: $spec_version,
+						..Default::default()
+					};
+				}
+
+				#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+				impl frame_system::Config for Runtime {
+					type Block = Block;
+					type Version = Version;
+					type RuntimeOrigin = RuntimeOrigin;
+					type RuntimeCall = RuntimeCall;
+					type RuntimeEvent = RuntimeEvent;
+					type PalletInfo = PalletInfo;
+				}
+
+				impl $toy::Config for Runtime {}
+
+				pub fn new_test_ext() -> sp_io::TestExternalities {
+					RuntimeGenesisConfig::default().build_storage().unwrap().into()
+				}
+			};
+		}
+
+		/// The runtime of the previous release.
+		pub mod old {
+			use super::*;
+
+			toy_runtime!(toy_v0, 1);
+		}
+
+		/// The current runtime, upgraded from [`old`].
+		pub mod new {
+			use super::*;
+
+			toy_runtime!(toy_v1, 2);
+		}
+
+		pub use toy_v1::MigrateToV1;
+
+		pub fn all_values_migrated() -> Result<(), String> {
+			for index in 0..3 {
+				toy_v1::Values::<new::Runtime>::get(index)
+					.ok_or_else(|| format!("value {} is not a `u64`", index))?;
+			}
+			Ok(())
+		}
+
+		/// The state written by the old release of the toy pallet in the old runtime.
+		pub fn recorded_fixture() -> StateFixture {
+			OldStateRecorder::<old::Runtime>::new()
+				.pallet::<old::Toy>()
+				.record(&mut old::new_test_ext(), || {
+					(0..3).for_each(|i| toy_v0::Values::<old::Runtime>::insert(i, i * 10))
+				})
+		}
+	}
+
+	#[test]
+	fn migration_validator_runs_against_recorded_fixture() {
+		use migration::*;
+
+		let fixture = recorded_fixture();
+		assert_eq!(fixture.spec_version, 1);
+		assert_eq!(fixture.storage_version("Toy"), Some(Some(StorageVersion::new(0))));
+
+		let path = std::env::temp_dir()
+			.join(format!("frame-system-mocking-fixture-{}", std::process::id()));
+		fixture.export(&path).unwrap();
+
+		let mut ext =
+			MigrationValidator::<new::Runtime, new::AllPalletsWithSystem>::from_fixture(&path)
+				.unwrap()
+				.expect_storage_version::<new::Toy>(StorageVersion::new(0))
+				.execute::<MigrateToV1<new::Runtime, 3>>(all_values_migrated)
+				.unwrap();
+		ext.execute_with(|| {
+			assert_eq!(new::Toy::on_chain_storage_version(), 1);
+			assert_eq!(toy_v1::Values::<new::Runtime>::get(2), Some(20));
+			let last = crate::LastRuntimeUpgrade::<new::Runtime>::get().unwrap();
+			assert_eq!(last.spec_version.0, 2);
+		});
+
+		// A migration forgetting the last entry is caught.
+		let err =
+			MigrationValidator::<new::Runtime, new::AllPalletsWithSystem>::from_fixture(&path)
+				.unwrap()
+				.execute::<MigrateToV1<new::Runtime, 2>>(all_values_migrated)
+				.unwrap_err();
+		assert!(matches!(err, MigrationValidatorError::Assertion(e) if e.contains("value 2")));
+
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn migration_validator_reports_storage_version_mismatch() {
+		use migration::*;
+
+		let err = MigrationValidator::<new::Runtime>::new(recorded_fixture())
+			.expect_storage_version::<new::Toy>(StorageVersion::new(1))
+			.execute::<MigrateToV1<new::Runtime, 3>>(all_values_migrated)
+			.unwrap_err();
+		assert!(matches!(
+			err,
+			MigrationValidatorError::StorageVersionMismatch { ref pallet, recorded, expected }
+				if pallet == "Toy" && recorded == Some(StorageVersion::new(0)) && expected == 1
+		));
+		assert_eq!(
+			err.to_string(),
+			"pallet `Toy` was recorded at storage version Some(StorageVersion(0)), but the \
+			 migration expects to start from StorageVersion(1)",
+		);
+
+		// The state of the current runtime is not upgraded.
+		let fixture =
+			OldStateRecorder::<new::Runtime>::new().record(&mut new::new_test_ext(), || ());
+		assert!(matches!(
+			MigrationValidator::<new::Runtime>::new(fixture)
+				.execute::<MigrateToV1<new::Runtime, 3>>(|| Ok(())),
+			Err(MigrationValidatorError::NotAnUpgrade { recorded: 2, current: 2 })
+		));
+	}
+
+	#[test]
+	fn migration_validator_checks_the_registered_invariants() {
+		use crate::mocking::register_invariant;
+		use migration::*;
+
+		old::new_test_ext().execute_with(|| {
+			register_invariant("values migrated", all_values_migrated);
+			let validator = || MigrationValidator::<new::Runtime>::new(recorded_fixture());
+			assert!(validator().execute::<MigrateToV1<new::Runtime, 3>>(|| Ok(())).is_ok());
+
+			let error = std::panic::catch_unwind(|| {
+				validator().execute::<MigrateToV1<new::Runtime, 2>>(|| Ok(()))
+			})
+			.unwrap_err();
+			assert_eq!(
+				error.downcast_ref::<String>().map(String::as_str),
+				Some("invariant `values migrated` violated after the migration: value 2 is not a `u64`")
+			);
+		});
+	}
+
+	#[test]
+	fn simulated_runtime_upgrade_happens_once_per_version() {
+		use migration::*;
+
+		let mut ext = sp_io::TestExternalities::new(Default::default());
+		ext.execute_with(|| {
+			for (key, value) in recorded_fixture().storage {
+				unhashed::put_raw(&key, &value);
+			}
+			type Migrations = (MigrateToV1<new::Runtime, 3>, new::AllPalletsWithSystem);
+
+			assert!(simulate_runtime_upgrade::<new::Runtime, Migrations>().unwrap().is_some());
+			assert_eq!(new::Toy::on_chain_storage_version(), 1);
+			assert_eq!(toy_v1::Values::<new::Runtime>::get(1), Some(10));
+
+			assert_eq!(simulate_runtime_upgrade::<new::Runtime, Migrations>().unwrap(), None);
+		});
+	}
+
+	#[cfg(feature = "try-runtime")]
+	#[test]
+	fn migration_validator_runs_try_state_after_the_upgrade() {
+		use frame_support::traits::{TryState, TryStateSelect};
+		use migration::*;
+
+		/// The pallets of the current runtime, with a `try_state` needing every value to be
+		/// migrated.
+		struct AllPallets;
+		impl OnRuntimeUpgrade for AllPallets {
+			fn try_on_runtime_upgrade(checks: bool) -> Result<Weight, sp_runtime::TryRuntimeError> {
+				new::AllPalletsWithSystem::try_on_runtime_upgrade(checks)
+			}
+		}
+		impl TryState<u64> for AllPallets {
+			fn try_state(n: u64, s: TryStateSelect) -> Result<(), sp_runtime::TryRuntimeError> {
+				new::AllPalletsWithSystem::try_state(n, s)?;
+				all_values_migrated().map_err(|_| "values left behind".into())
+			}
+		}
+
+		let validator = || MigrationValidator::<new::Runtime, AllPallets>::new(recorded_fixture());
+		assert!(validator().execute::<MigrateToV1<new::Runtime, 3>>(|| Ok(())).is_ok());
+		// The assertions are not even run when `try_state` fails.
+		let err = validator()
+			.execute::<MigrateToV1<new::Runtime, 2>>(|| panic!("assertions run"))
+			.unwrap_err();
+		assert!(
+			matches!(err, MigrationValidatorError::Migration(ref e) if e.contains("values left behind")),
+			"{}",
+			err
+		);
+
+		// Without the migration, the storage version check of the toy pallet fails.
+		let err = validator().execute::<()>(|| Ok(())).unwrap_err();
+		assert!(
+			matches!(err, MigrationValidatorError::Migration(ref e) if e.contains("storage version")),
+			"{}",
+			err
+		);
+	}
+}
@@ -0,0 +1,134 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The mocking helpers that need `std`, e.g. to build [`sp_io::TestExternalities`].
+
+use crate::Config;
+use frame_support::{
+	storage::{storage_prefix, unhashed},
+	traits::PalletInfoAccess,
+};
+use sp_externalities::{Extension, ExternalitiesExt};
+use std::collections::BTreeMap;
+
+mod account_lifecycle;
+mod block_builder;
+mod bound_shrink;
+mod db_ops;
+mod determinism;
+mod dispatch_info;
+mod dispatcher;
+mod encoding_guard;
+mod equivalence;
+mod externalities;
+mod golden;
+mod growth;
+mod invariants;
+mod iteration_order;
+mod migration;
+mod origin;
+mod pool;
+mod serial;
+mod signed;
+mod stepped_migration;
+mod swappable;
+mod tasks;
+mod time_travel;
+
+pub use account_lifecycle::*;
+pub use block_builder::*;
+pub use bound_shrink::*;
+pub use db_ops::*;
+pub use determinism::*;
+pub use dispatch_info::*;
+pub use dispatcher::*;
+pub use encoding_guard::*;
+pub use equivalence::*;
+pub use externalities::*;
+pub use golden::*;
+pub use growth::*;
+pub use invariants::*;
+pub use iteration_order::*;
+pub use migration::*;
+pub use origin::*;
+pub use pool::*;
+pub use serial::*;
+pub use signed::*;
+pub use stepped_migration::*;
+pub use swappable::*;
+pub use tasks::*;
+pub use time_travel::*;
+
+/// Access the extension `E` of the current externalities, registering a default one if needed.
+///
+/// # Panics
+///
+/// Outside of externalities, naming `what` in the message.
+fn with_extension<E: Extension + Default, R>(what: &str, f: impl FnOnce(&mut E) -> R) -> R {
+	sp_externalities::with_externalities(|mut ext| {
+		if ext.extension::<E>().is_none() {
+			ext.register_extension(E::default())
+				.expect("the extension is registered only once; qed");
+		}
+		f(ext.extension::<E>().expect("the extension was just registered; qed"))
+	})
+	.unwrap_or_else(|| panic!("{} are only available within externalities", what))
+}
+
+/// Collect every key/value pair of the main trie visible in the current externalities.
+fn storage_pairs() -> Vec<(Vec<u8>, Vec<u8>)> {
+	let mut pairs = Vec::new();
+	let mut key = Vec::new();
+	while let Some(next) = sp_io::storage::next_key(&key) {
+		if let Some(value) = unhashed::get_raw(&next) {
+			pairs.push((next.clone(), value));
+		}
+		key = next;
+	}
+	pairs
+}
+
+/// The first elements of `a` and `b` that differ.
+fn first_difference<'a, I: PartialEq>(a: &'a [I], b: &'a [I]) -> (Option<&'a I>, Option<&'a I>) {
+	let position = a.iter().zip(b).position(|(a, b)| a != b).unwrap_or(a.len().min(b.len()));
+	(a.get(position), b.get(position))
+}
+
+/// The prefix of `storage` of the System pallet, under the name given to it by the runtime.
+fn system_storage_prefix<T: Config>(storage: &str) -> [u8; 32] {
+	storage_prefix(<crate::Pallet<T> as PalletInfoAccess>::name().as_bytes(), storage.as_bytes())
+}
+
+/// The prefixes of the storage of the System pallet holding the events.
+fn events_prefixes<T: Config>() -> [[u8; 32]; 3] {
+	[
+		system_storage_prefix::<T>("Events"),
+		system_storage_prefix::<T>("EventTopics"),
+		system_storage_prefix::<T>("EventCount"),
+	]
+}
+
+/// The keys whose value changed since `before` was collected, with their new value if any.
+fn storage_mutations(before: &BTreeMap<Vec<u8>, Vec<u8>>) -> BTreeMap<Vec<u8>, Option<Vec<u8>>> {
+	let after = storage_pairs().into_iter().collect::<BTreeMap<_, _>>();
+	before
+		.keys()
+		.chain(after.keys())
+		.filter(|key| before.get(*key) != after.get(*key))
+		.map(|key| (key.clone(), after.get(key).cloned()))
+		.collect()
+}
@@ -0,0 +1,260 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A configurable [`EnsureOriginWithArg`], with the origins it accepts set per test.
+
+use super::with_extension;
+use crate::{Config, RawOrigin};
+#[cfg(feature = "runtime-benchmarks")]
+use codec::Decode;
+use frame_support::traits::EnsureOriginWithArg;
+use sp_externalities::Extension;
+use std::{
+	any::{Any, TypeId},
+	collections::BTreeMap,
+	fmt,
+	marker::PhantomData,
+};
+
+/// Matches the system origin of a query to [`MockEnsureOriginWithArg`].
+type OriginMatcher<AccountId> = Box<dyn Fn(&RawOrigin<AccountId>) -> bool + Send + Sync>;
+
+/// Matches the argument of a query to [`MockEnsureOriginWithArg`].
+type ArgMatcher<Arg> = Box<dyn Fn(&Arg) -> bool + Send + Sync>;
+
+/// The rules and recorded queries of one [`MockEnsureOriginWithArg`] type.
+struct OriginRules<AccountId, Arg> {
+	rules: Vec<(bool, OriginMatcher<AccountId>, ArgMatcher<Arg>)>,
+	queries: Vec<(Option<RawOrigin<AccountId>>, Arg)>,
+}
+
+impl<AccountId, Arg> OriginRules<AccountId, Arg> {
+	fn is_allowed(&self, origin: &RawOrigin<AccountId>, arg: &Arg) -> bool {
+		let rule = self
+			.rules
+			.iter()
+			.find(|(_, origin_matches, arg_matches)| origin_matches(origin) && arg_matches(arg));
+		matches!(rule, Some((true, _, _)))
+	}
+}
+
+/// The [`OriginRules`] of every [`MockEnsureOriginWithArg`] type used in the externalities.
+#[derive(Default)]
+struct OriginRulesRegistry(BTreeMap<TypeId, Box<dyn Any + Send>>);
+
+impl Extension for OriginRulesRegistry {
+	fn as_mut_any(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+/// An [`EnsureOriginWithArg`] whose decisions are programmed by the test.
+///
+/// Rules added with [`Self::allow`] and [`Self::deny`] are evaluated in order, the first one
+/// matching both the origin and the argument decides; without a matching rule the origin is
+/// rejected. Only system origins ([`RawOrigin`]) can be matched. Every query is recorded and can be
+/// inspected with [`Self::origin_queries`].
+///
+/// Rules and queries are kept in the externalities, so they are dropped with them and never
+/// observed by the next test. On success, the signer of the origin is returned (`None` for root and
+/// unsigned origins).
+pub struct MockEnsureOriginWithArg<T, Arg>(PhantomData<(T, Arg)>);
+
+impl<T: Config, Arg: Clone + Send + 'static> MockEnsureOriginWithArg<T, Arg> {
+	/// Access the rules of this type in the current externalities.
+	///
+	/// # Panics
+	///
+	/// Outside of externalities.
+	fn with_rules<R>(f: impl FnOnce(&mut OriginRules<T::AccountId, Arg>) -> R) -> R {
+		with_extension("origin rules", |OriginRulesRegistry(rules)| {
+			let rules = rules.entry(TypeId::of::<Self>()).or_insert_with(|| {
+				Box::new(OriginRules::<T::AccountId, Arg> {
+					rules: Vec::new(),
+					queries: Vec::new(),
+				})
+			});
+			f(rules.downcast_mut().expect("entries are keyed by their type; qed"))
+		})
+	}
+
+	/// Accept origins matching `origin` for arguments matching `arg`.
+	pub fn allow(
+		origin: impl Fn(&RawOrigin<T::AccountId>) -> bool + Send + Sync + 'static,
+		arg: impl Fn(&Arg) -> bool + Send + Sync + 'static,
+	) {
+		Self::with_rules(|rules| rules.rules.push((true, Box::new(origin), Box::new(arg))));
+	}
+
+	/// Reject origins matching `origin` for arguments matching `arg`.
+	pub fn deny(
+		origin: impl Fn(&RawOrigin<T::AccountId>) -> bool + Send + Sync + 'static,
+		arg: impl Fn(&Arg) -> bool + Send + Sync + 'static,
+	) {
+		Self::with_rules(|rules| rules.rules.push((false, Box::new(origin), Box::new(arg))));
+	}
+
+	/// Remove all rules, rejecting every origin from now on.
+	pub fn deny_all() {
+		Self::with_rules(|rules| rules.rules.clear());
+	}
+
+	/// All queries made so far, in order.
+	///
+	/// The origin is `None` if it wasn't a system origin.
+	pub fn origin_queries() -> Vec<(Option<RawOrigin<T::AccountId>>, Arg)> {
+		Self::with_rules(|rules| rules.queries.clone())
+	}
+
+	/// Remove all rules and recorded queries.
+	pub fn reset() {
+		Self::with_rules(|rules| {
+			rules.rules.clear();
+			rules.queries.clear();
+		});
+	}
+}
+
+impl<T: Config, Arg: Clone + Send + 'static> EnsureOriginWithArg<T::RuntimeOrigin, Arg>
+	for MockEnsureOriginWithArg<T, Arg>
+{
+	type Success = Option<T::AccountId>;
+
+	fn try_origin(o: T::RuntimeOrigin, a: &Arg) -> Result<Self::Success, T::RuntimeOrigin> {
+		let raw: Result<RawOrigin<T::AccountId>, _> = o.clone().into();
+		Self::with_rules(|rules| {
+			rules.queries.push((raw.as_ref().ok().cloned(), a.clone()));
+			match raw {
+				Ok(raw) if rules.is_allowed(&raw, a) => Ok(match raw {
+					RawOrigin::Signed(who) => Some(who),
+					RawOrigin::Root | RawOrigin::None => None,
+				}),
+				_ => Err(o),
+			}
+		})
+	}
+
+	#[cfg(feature = "runtime-benchmarks")]
+	fn try_successful_origin(a: &Arg) -> Result<T::RuntimeOrigin, ()> {
+		let candidates = [
+			RawOrigin::Root,
+			RawOrigin::Signed(
+				T::AccountId::decode(&mut sp_runtime::traits::TrailingZeroInput::zeroes())
+					.expect("infinite length input; no invalid inputs for type; qed"),
+			),
+			RawOrigin::None,
+		];
+		Self::with_rules(|rules| candidates.into_iter().find(|o| rules.is_allowed(o, a)))
+			.map(Into::into)
+			.ok_or(())
+	}
+}
+
+/// Assert that `E` accepts `origin` for `arg`.
+pub fn assert_origin_with_arg_allowed<E, O, Arg>(origin: O, arg: &Arg)
+where
+	E: EnsureOriginWithArg<O, Arg>,
+	Arg: fmt::Debug,
+{
+	assert!(E::try_origin(origin, arg).is_ok(), "origin was rejected for argument {:?}", arg);
+}
+
+/// Assert that `E` rejects `origin` for `arg`.
+pub fn assert_origin_with_arg_denied<E, O, Arg>(origin: O, arg: &Arg)
+where
+	E: EnsureOriginWithArg<O, Arg>,
+	Arg: fmt::Debug,
+{
+	assert!(E::try_origin(origin, arg).is_err(), "origin was accepted for argument {:?}", arg);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, Test};
+
+	#[test]
+	fn mock_ensure_origin_with_arg_follows_rules() {
+		use crate::mock::RuntimeOrigin;
+		type AssetAdmin = MockEnsureOriginWithArg<Test, u32>;
+
+		/// A dispatchable guarded by an asset specific admin origin.
+		fn set_asset_metadata(origin: RuntimeOrigin, asset: u32) -> sp_runtime::DispatchResult {
+			AssetAdmin::ensure_origin(origin, &asset)?;
+			Ok(())
+		}
+
+		new_test_ext().execute_with(|| {
+			AssetAdmin::allow(|o| *o == RawOrigin::Signed(1), |asset| *asset == 1);
+
+			assert_eq!(set_asset_metadata(RuntimeOrigin::signed(1), 1), Ok(()));
+			assert!(set_asset_metadata(RuntimeOrigin::signed(1), 2).is_err());
+			assert!(set_asset_metadata(RuntimeOrigin::signed(2), 1).is_err());
+			assert_eq!(AssetAdmin::try_origin(RuntimeOrigin::signed(1), &1).ok(), Some(Some(1)));
+			assert_origin_with_arg_denied::<AssetAdmin, _, _>(RuntimeOrigin::root(), &1);
+
+			assert_eq!(
+				AssetAdmin::origin_queries(),
+				vec![
+					(Some(RawOrigin::Signed(1)), 1),
+					(Some(RawOrigin::Signed(1)), 2),
+					(Some(RawOrigin::Signed(2)), 1),
+					(Some(RawOrigin::Signed(1)), 1),
+					(Some(RawOrigin::Root), 1),
+				]
+			);
+		});
+	}
+
+	#[test]
+	fn mock_ensure_origin_with_arg_first_matching_rule_wins() {
+		use crate::mock::RuntimeOrigin;
+		type AssetAdmin = MockEnsureOriginWithArg<Test, u32>;
+
+		new_test_ext().execute_with(|| {
+			AssetAdmin::deny(|o| *o == RawOrigin::Root, |asset| *asset == 0);
+			AssetAdmin::allow(|o| *o == RawOrigin::Root, |_| true);
+			assert_origin_with_arg_denied::<AssetAdmin, _, _>(RuntimeOrigin::root(), &0);
+			assert_origin_with_arg_allowed::<AssetAdmin, _, _>(RuntimeOrigin::root(), &7);
+
+			AssetAdmin::deny_all();
+			assert_origin_with_arg_denied::<AssetAdmin, _, _>(RuntimeOrigin::root(), &7);
+			assert_eq!(AssetAdmin::origin_queries().len(), 3);
+
+			// Different argument types are programmed independently.
+			assert!(MockEnsureOriginWithArg::<Test, u64>::origin_queries().is_empty());
+			AssetAdmin::reset();
+			assert!(AssetAdmin::origin_queries().is_empty());
+		});
+	}
+
+	#[test]
+	fn mock_ensure_origin_with_arg_rules_do_not_outlive_the_externalities() {
+		use crate::mock::RuntimeOrigin;
+		type AssetAdmin = MockEnsureOriginWithArg<Test, u32>;
+
+		new_test_ext().execute_with(|| {
+			AssetAdmin::allow(|o| *o == RawOrigin::Root, |_| true);
+			assert_origin_with_arg_allowed::<AssetAdmin, _, _>(RuntimeOrigin::root(), &1);
+		});
+
+		new_test_ext().execute_with(|| {
+			assert!(AssetAdmin::origin_queries().is_empty());
+			assert_origin_with_arg_denied::<AssetAdmin, _, _>(RuntimeOrigin::root(), &1);
+		});
+	}
+}
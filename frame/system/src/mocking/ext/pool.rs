@@ -0,0 +1,338 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Simulate how the transaction pool orders, replaces and drops unsigned transactions.
+
+use crate::Config;
+use sp_runtime::{
+	traits::{One, UniqueSaturatedInto, ValidateUnsigned},
+	transaction_validity::{
+		InvalidTransaction, TransactionPriority, TransactionSource, TransactionTag,
+		TransactionValidityError, ValidTransaction,
+	},
+};
+use std::{cmp::Reverse, collections::BTreeSet, marker::PhantomData};
+
+/// Why a [`PoolSimulator`] rejected a transaction.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PoolError {
+	/// The transaction failed validation.
+	Invalid(TransactionValidityError),
+	/// A transaction providing the same tag is in the pool and has at least the same priority.
+	TooLowPriority {
+		/// The priority of the transaction in the pool.
+		old: TransactionPriority,
+		/// The priority of the rejected transaction.
+		new: TransactionPriority,
+	},
+}
+
+/// A transaction in a [`PoolSimulator`].
+#[derive(Clone, Debug)]
+pub struct PooledTransaction<Call> {
+	/// The call of the transaction.
+	pub call: Call,
+	/// Where the transaction was submitted from.
+	pub source: TransactionSource,
+	/// The validity returned by `validate_unsigned`.
+	pub validity: ValidTransaction,
+	/// The block the transaction was validated at.
+	pub validated_at: u64,
+}
+
+/// Simulates how the transaction pool handles the unsigned transactions validated by `V`.
+///
+/// Like the real pool, transactions are ordered by priority once all the tags they require are
+/// provided, either by another ready transaction or by a transaction already included in a
+/// block. A transaction providing the same tag as a pooled one replaces it only with a higher
+/// priority, and transactions are dropped once their longevity elapsed.
+pub struct PoolSimulator<T, V: ValidateUnsigned> {
+	pool: Vec<PooledTransaction<V::Call>>,
+	included_tags: BTreeSet<TransactionTag>,
+	_phantom: PhantomData<T>,
+}
+
+impl<T: Config, V: ValidateUnsigned> Default for PoolSimulator<T, V> {
+	fn default() -> Self {
+		Self { pool: Vec::new(), included_tags: BTreeSet::new(), _phantom: PhantomData }
+	}
+}
+
+impl<T: Config, V: ValidateUnsigned> PoolSimulator<T, V>
+where
+	V::Call: Clone,
+{
+	/// Create an empty pool.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	fn block_number() -> u64 {
+		crate::Pallet::<T>::block_number().unique_saturated_into()
+	}
+
+	/// Validate `call` as submitted from `source` and put it into the pool.
+	pub fn submit_unsigned(
+		&mut self,
+		call: V::Call,
+		source: TransactionSource,
+	) -> Result<(), PoolError> {
+		let validity = V::validate_unsigned(source, &call).map_err(PoolError::Invalid)?;
+		if validity.provides.iter().any(|tag| self.included_tags.contains(tag)) {
+			return Err(PoolError::Invalid(InvalidTransaction::Stale.into()))
+		}
+
+		let replaced = |pooled: &PooledTransaction<V::Call>| {
+			pooled.validity.provides.iter().any(|tag| validity.provides.contains(tag))
+		};
+		if let Some(old) = self
+			.pool
+			.iter()
+			.filter(|pooled| replaced(pooled))
+			.map(|pooled| pooled.validity.priority)
+			.max()
+		{
+			if old >= validity.priority {
+				return Err(PoolError::TooLowPriority { old, new: validity.priority })
+			}
+		}
+		self.pool.retain(|pooled| !replaced(pooled));
+
+		self.pool.push(PooledTransaction {
+			call,
+			source,
+			validity,
+			validated_at: Self::block_number(),
+		});
+		Ok(())
+	}
+
+	/// Split the pool into the ready transactions, in inclusion order, and the future ones.
+	fn split(&self) -> (Vec<usize>, Vec<usize>) {
+		let mut provided = self.included_tags.clone();
+		let mut ready = Vec::new();
+		let mut pending = (0..self.pool.len()).collect::<Vec<_>>();
+		loop {
+			// The highest priority wins, ties are resolved by submission order.
+			let best = pending
+				.iter()
+				.enumerate()
+				.filter(|(_, index)| {
+					self.pool[**index].validity.requires.iter().all(|tag| provided.contains(tag))
+				})
+				.min_by_key(|(_, index)| (Reverse(self.pool[**index].validity.priority), **index))
+				.map(|(position, _)| position);
+			let Some(position) = best else { break };
+			let index = pending.remove(position);
+			provided.extend(self.pool[index].validity.provides.iter().cloned());
+			ready.push(index);
+		}
+
+		(ready, pending)
+	}
+
+	/// The transactions ready for inclusion, in the order they would be included.
+	pub fn ready(&self) -> Vec<PooledTransaction<V::Call>> {
+		self.split().0.into_iter().map(|index| self.pool[index].clone()).collect()
+	}
+
+	/// The transactions waiting for the tags they require.
+	pub fn future(&self) -> Vec<PooledTransaction<V::Call>> {
+		self.split().1.into_iter().map(|index| self.pool[index].clone()).collect()
+	}
+
+	/// Include the next ready transaction in the current block.
+	///
+	/// The transaction leaves the pool and `pre_dispatch` is run for it. If that succeeds, the
+	/// tags it provides are satisfied from now on. Returns `None` if no transaction is ready.
+	pub fn include_next_in_block(
+		&mut self,
+	) -> Option<(V::Call, Result<(), TransactionValidityError>)> {
+		let index = *self.split().0.first()?;
+		let pooled = self.pool.remove(index);
+		let result = V::pre_dispatch(&pooled.call);
+		if result.is_ok() {
+			self.included_tags.extend(pooled.validity.provides);
+		}
+		Some((pooled.call, result))
+	}
+
+	/// Move to the next block, dropping the transactions whose longevity elapsed.
+	///
+	/// Returns the dropped transactions.
+	pub fn roll_block(&mut self) -> Vec<PooledTransaction<V::Call>> {
+		crate::Pallet::<T>::set_block_number(crate::Pallet::<T>::block_number() + One::one());
+		let now = Self::block_number();
+		let (expired, pool) = std::mem::take(&mut self.pool).into_iter().partition(|pooled| {
+			pooled.validated_at.saturating_add(pooled.validity.longevity) <= now
+		});
+		self.pool = pool;
+		expired
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, Test};
+
+	mod pool {
+		use super::*;
+		use sp_runtime::transaction_validity::TransactionValidity;
+
+		/// The unsigned calls of a toy pallet, `nonce`s of a `who` must be included in order.
+		#[derive(Clone, PartialEq, Debug)]
+		pub enum Call {
+			Ping { who: u8, nonce: u8, priority: u64 },
+			LocalOnly,
+		}
+
+		pub struct Toy;
+
+		impl ValidateUnsigned for Toy {
+			type Call = Call;
+
+			fn validate_unsigned(source: TransactionSource, call: &Call) -> TransactionValidity {
+				match call {
+					Call::Ping { who, nonce, priority } => {
+						let mut builder = ValidTransaction::with_tag_prefix("Toy")
+							.priority(*priority)
+							.and_provides((who, nonce))
+							.longevity(3);
+						if *nonce > 0 {
+							builder = builder.and_requires((who, nonce - 1));
+						}
+						builder.build()
+					},
+					Call::LocalOnly if source == TransactionSource::External =>
+						InvalidTransaction::Call.into(),
+					Call::LocalOnly =>
+						ValidTransaction::with_tag_prefix("Toy").and_provides("local").build(),
+				}
+			}
+
+			fn pre_dispatch(call: &Call) -> Result<(), TransactionValidityError> {
+				match call {
+					Call::Ping { priority: 0, .. } => Err(InvalidTransaction::Payment.into()),
+					_ => Ok(()),
+				}
+			}
+		}
+
+		pub fn ping(who: u8, nonce: u8, priority: u64) -> Call {
+			Call::Ping { who, nonce, priority }
+		}
+
+		pub fn calls(transactions: Vec<PooledTransaction<Call>>) -> Vec<Call> {
+			transactions.into_iter().map(|pooled| pooled.call).collect()
+		}
+	}
+
+	#[test]
+	fn pool_simulator_orders_by_tags_and_priority() {
+		use pool::*;
+
+		new_test_ext().execute_with(|| {
+			let mut pool = PoolSimulator::<Test, Toy>::new();
+			let external = TransactionSource::External;
+			pool.submit_unsigned(ping(1, 1, 100), external).unwrap();
+			pool.submit_unsigned(ping(2, 0, 10), external).unwrap();
+			pool.submit_unsigned(ping(1, 2, 100), external).unwrap();
+			assert_eq!(calls(pool.ready()), vec![ping(2, 0, 10)]);
+			assert_eq!(calls(pool.future()), vec![ping(1, 1, 100), ping(1, 2, 100)]);
+
+			pool.submit_unsigned(ping(1, 0, 1), external).unwrap();
+			// `(1, 0)` unlocks the higher priority transactions depending on it.
+			assert_eq!(
+				calls(pool.ready()),
+				vec![ping(2, 0, 10), ping(1, 0, 1), ping(1, 1, 100), ping(1, 2, 100)]
+			);
+			assert!(pool.future().is_empty());
+
+			assert_eq!(pool.include_next_in_block(), Some((ping(2, 0, 10), Ok(()))));
+			assert_eq!(pool.include_next_in_block(), Some((ping(1, 0, 1), Ok(()))));
+			// Once included, the tag stays satisfied and can't be provided again.
+			assert_eq!(
+				pool.submit_unsigned(ping(1, 0, 50), external),
+				Err(PoolError::Invalid(InvalidTransaction::Stale.into()))
+			);
+			assert_eq!(calls(pool.ready()), vec![ping(1, 1, 100), ping(1, 2, 100)]);
+		});
+	}
+
+	#[test]
+	fn pool_simulator_replaces_by_priority() {
+		use pool::*;
+
+		new_test_ext().execute_with(|| {
+			let mut pool = PoolSimulator::<Test, Toy>::new();
+			let local = TransactionSource::Local;
+			pool.submit_unsigned(ping(1, 0, 0), local).unwrap();
+			assert_eq!(
+				pool.submit_unsigned(ping(1, 0, 0), local),
+				Err(PoolError::TooLowPriority { old: 0, new: 0 })
+			);
+			pool.submit_unsigned(ping(1, 0, 5), local).unwrap();
+			assert_eq!(calls(pool.ready()), vec![ping(1, 0, 5)]);
+			assert_eq!(pool.include_next_in_block(), Some((ping(1, 0, 5), Ok(()))));
+			assert_eq!(pool.include_next_in_block(), None);
+		});
+	}
+
+	#[test]
+	fn pool_simulator_honors_source_and_pre_dispatch() {
+		use pool::*;
+
+		new_test_ext().execute_with(|| {
+			let mut pool = PoolSimulator::<Test, Toy>::new();
+			assert_eq!(
+				pool.submit_unsigned(Call::LocalOnly, TransactionSource::External),
+				Err(PoolError::Invalid(InvalidTransaction::Call.into()))
+			);
+			pool.submit_unsigned(Call::LocalOnly, TransactionSource::Local).unwrap();
+			pool.submit_unsigned(ping(1, 0, 0), TransactionSource::InBlock).unwrap();
+
+			assert_eq!(pool.include_next_in_block(), Some((Call::LocalOnly, Ok(()))));
+			// A failing `pre_dispatch` doesn't satisfy the provided tags.
+			assert_eq!(
+				pool.include_next_in_block(),
+				Some((ping(1, 0, 0), Err(InvalidTransaction::Payment.into())))
+			);
+			pool.submit_unsigned(ping(1, 0, 1), TransactionSource::InBlock).unwrap();
+		});
+	}
+
+	#[test]
+	fn pool_simulator_drops_expired_transactions() {
+		use pool::*;
+
+		new_test_ext().execute_with(|| {
+			crate::Pallet::<Test>::set_block_number(10);
+			let mut pool = PoolSimulator::<Test, Toy>::new();
+			pool.submit_unsigned(ping(1, 0, 1), TransactionSource::External).unwrap();
+			pool.roll_block();
+			pool.submit_unsigned(ping(2, 0, 1), TransactionSource::External).unwrap();
+
+			assert!(pool.roll_block().is_empty());
+			let expired = pool.roll_block();
+			assert_eq!(calls(expired), vec![ping(1, 0, 1)]);
+			assert_eq!(crate::Pallet::<Test>::block_number(), 13);
+			assert_eq!(calls(pool.ready()), vec![ping(2, 0, 1)]);
+			assert_eq!(calls(pool.roll_block()), vec![ping(2, 0, 1)]);
+		});
+	}
+}
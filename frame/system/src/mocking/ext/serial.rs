@@ -0,0 +1,187 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Serialize the tests sharing a static, and detect the statics shared by tests running in
+//! parallel.
+
+use std::{
+	collections::BTreeMap,
+	fmt,
+	sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// The named locks handed out by [`serial_lock`].
+static SERIAL_GROUPS: Mutex<BTreeMap<&'static str, &'static Mutex<()>>> =
+	Mutex::new(BTreeMap::new());
+
+/// Acquire the process wide lock of `group`.
+///
+/// Tests touching the same process wide state (e.g. a `static` cache used by the mock runtime)
+/// should acquire the lock of the same group, so they can't run at the same time while everything
+/// else keeps running in parallel. A test panicking while holding the lock doesn't poison it for
+/// the others. Prefer [`serial_guard!`](crate::serial_guard) in tests.
+pub fn serial_lock(group: &'static str) -> MutexGuard<'static, ()> {
+	let lock: &'static Mutex<()> = SERIAL_GROUPS
+		.lock()
+		.unwrap_or_else(PoisonError::into_inner)
+		.entry(group)
+		.or_insert_with(|| Box::leak(Box::new(Mutex::new(()))));
+	lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Hold the [`serial_lock`] of the given group until the end of the current scope.
+///
+/// ```
+/// // Other tests using `serial_guard!(global_cache)` won't run until this scope ends.
+/// frame_system::serial_guard!(global_cache);
+/// ```
+#[macro_export]
+macro_rules! serial_guard {
+	($group:ident) => {
+		let _serial_guard = $crate::mocking::serial_lock(::core::stringify!($group));
+	};
+}
+
+/// Declare a test which holds the [`serial_lock`] of `group` and runs in externalities.
+///
+/// The externalities are built by `new_test_ext()`, which must be in scope, unless an `ext`
+/// expression is given.
+///
+/// ```
+/// use frame_system::mocking::serial_mock_test;
+///
+/// fn new_test_ext() -> sp_io::TestExternalities {
+///     Default::default()
+/// }
+///
+/// // Other tests of the `global_cache` group won't run at the same time.
+/// #[serial_mock_test(global_cache)]
+/// fn works() {
+///     assert!(sp_io::storage::get(b"key").is_none());
+/// }
+///
+/// #[serial_mock_test(global_cache, ext = sp_io::TestExternalities::new_empty())]
+/// #[should_panic]
+/// fn fails() {
+///     panic!("runs in externalities");
+/// }
+/// ```
+pub use frame_support_procedural::serial_mock_test;
+
+/// Check that `scenario` doesn't leak state to later runs of itself.
+///
+/// The scenario runs twice, each time on a fresh thread like separate tests would, holding the
+/// [`serial_lock`] of `group` during each run but releasing it in between. Thread local state
+/// (like `parameter_types!` statics) is reset that way, so any difference between the two
+/// observations means the scenario left state behind in something shared by the whole process.
+///
+/// # Panics
+///
+/// If both runs observe different values.
+pub fn assert_no_shared_static_leak<R>(group: &'static str, scenario: impl Fn() -> R + Sync)
+where
+	R: PartialEq + fmt::Debug + Send,
+{
+	let run = || {
+		std::thread::scope(|s| {
+			s.spawn(|| {
+				let _guard = serial_lock(group);
+				scenario()
+			})
+			.join()
+			.expect("scenario panicked")
+		})
+	};
+
+	let first = run();
+	let second = run();
+	assert_eq!(
+		first, second,
+		"state leaked between two runs of the same scenario in serial group `{}`",
+		group,
+	);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{
+		self as frame_system,
+		mock::{new_test_ext, Test},
+	};
+
+	#[test]
+	fn serial_guard_serializes_tests_of_a_group() {
+		static SHARED: Mutex<Option<u32>> = Mutex::new(None);
+
+		let handles = (0..4)
+			.map(|id| {
+				std::thread::spawn(move || {
+					crate::serial_guard!(shared_value);
+					*SHARED.lock().unwrap() = Some(id);
+					std::thread::sleep(std::time::Duration::from_millis(10));
+					assert_eq!(*SHARED.lock().unwrap(), Some(id));
+				})
+			})
+			.collect::<Vec<_>>();
+		handles.into_iter().for_each(|h| h.join().unwrap());
+	}
+
+	#[test]
+	fn serial_guard_recovers_from_panicking_test() {
+		let _ = std::thread::spawn(|| {
+			crate::serial_guard!(poisoned);
+			panic!("test failed while holding the guard");
+		})
+		.join();
+
+		crate::serial_guard!(poisoned);
+	}
+
+	#[serial_mock_test(shared_value)]
+	fn serial_mock_test_runs_in_ext() {
+		assert_eq!(crate::Pallet::<Test>::block_number(), 0);
+	}
+
+	#[serial_mock_test(shared_value, ext = sp_io::TestExternalities::new_empty())]
+	fn serial_mock_test_runs_in_the_given_ext() {
+		// Set by the genesis of `new_test_ext()`.
+		assert_eq!(frame_system::Pallet::<Test>::block_hash(0), Default::default());
+	}
+
+	#[test]
+	fn leak_detector_accepts_thread_local_state() {
+		frame_support::parameter_types! {
+			static Counter: u32 = 0;
+		}
+
+		assert_no_shared_static_leak("leak_detector", || {
+			Counter::mutate(|c| *c += 1);
+			Counter::get()
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "state leaked between two runs")]
+	fn leak_detector_catches_unguarded_static() {
+		static COUNTER: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+		assert_no_shared_static_leak("leak_detector", || {
+			COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
+		});
+	}
+}
@@ -0,0 +1,285 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sign and execute extrinsics with mock signatures.
+
+use super::{always_written, dispatch_noop_on_error};
+use crate::Config;
+use codec::Encode;
+use frame_support::dispatch::{DispatchInfo, GetDispatchInfo, PostDispatchInfo};
+use sp_runtime::{
+	generic::SignedPayload,
+	testing::TestSignature,
+	traits::{Checkable, Dispatchable, IdentityLookup, Member, SignedExtension},
+	transaction_validity::TransactionValidityError,
+	ApplyExtrinsicResult,
+};
+
+/// A signed extrinsic to be used in tests, for runtimes whose accounts are `u64`.
+///
+/// The signature of the account `who` is a [`TestSignature`] of `who` over the signed payload,
+/// see [`sign_extrinsic`].
+pub type MockSignedExtrinsic<T, Extra> =
+	crate::mocking::MockUncheckedExtrinsic<T, TestSignature, Extra>;
+
+/// Sign `call` as `who`, with the signed extensions `extra`.
+pub fn sign_extrinsic<T, Extra>(
+	who: u64,
+	call: T::RuntimeCall,
+	extra: Extra,
+) -> Result<MockSignedExtrinsic<T, Extra>, TransactionValidityError>
+where
+	T: Config<AccountId = u64>,
+	Extra: SignedExtension,
+{
+	let payload = SignedPayload::new(call, extra)?;
+	let signature = TestSignature(who, payload.encode());
+	let (call, extra, _) = payload.deconstruct();
+	Ok(MockSignedExtrinsic::<T, Extra>::new_signed(call, who, signature, extra))
+}
+
+/// Sign `call` as `who` with the signed extensions `extra` and apply it, like the `Executive`
+/// would in a block.
+///
+/// The signature is checked, the extensions are validated and their `pre_dispatch` and
+/// `post_dispatch` executed around the dispatch, and the extrinsic is noted by the System pallet.
+///
+/// A failed dispatch must not leave storage mutations behind, see
+/// [`MockDispatcher`](super::MockDispatcher); use [`sign_and_execute_with`] to skip the check.
+///
+/// # Panics
+///
+/// If the dispatch fails and mutated the storage.
+#[track_caller]
+pub fn sign_and_execute<T, Extra>(
+	who: u64,
+	call: T::RuntimeCall,
+	extra: Extra,
+) -> ApplyExtrinsicResult
+where
+	T: Config<AccountId = u64>,
+	T::RuntimeCall:
+		Dispatchable<Info = DispatchInfo, PostInfo = PostDispatchInfo> + GetDispatchInfo + Member,
+	T::RuntimeOrigin: From<Option<u64>>,
+	Extra: SignedExtension<AccountId = u64, Call = T::RuntimeCall>,
+{
+	sign_and_execute_with::<T, Extra>(true, who, call, extra)
+}
+
+/// Like [`sign_and_execute`], checking that a failed dispatch doesn't mutate the storage only if
+/// `strict_noop_on_error`.
+#[track_caller]
+pub fn sign_and_execute_with<T, Extra>(
+	strict_noop_on_error: bool,
+	who: u64,
+	call: T::RuntimeCall,
+	extra: Extra,
+) -> ApplyExtrinsicResult
+where
+	T: Config<AccountId = u64>,
+	T::RuntimeCall:
+		Dispatchable<Info = DispatchInfo, PostInfo = PostDispatchInfo> + GetDispatchInfo + Member,
+	T::RuntimeOrigin: From<Option<u64>>,
+	Extra: SignedExtension<AccountId = u64, Call = T::RuntimeCall>,
+{
+	let extrinsic = sign_extrinsic::<T, Extra>(who, call, extra)?;
+	let encoded = extrinsic.encode();
+	let checked = extrinsic.check(&IdentityLookup::<u64>::default())?;
+	let info = checked.get_dispatch_info();
+	crate::Pallet::<T>::note_extrinsic(encoded.clone());
+
+	// Applied like `CheckedExtrinsic::apply`, but checking the dispatch alone.
+	let (who, extra) = checked.signed.expect("the extrinsic is signed; qed");
+	let pre = extra.pre_dispatch(&who, &checked.function, &info, encoded.len())?;
+	let result = dispatch_noop_on_error(
+		strict_noop_on_error,
+		&always_written::<T>(),
+		checked.function,
+		Some(who).into(),
+	);
+	let post_info = result.unwrap_or_else(|err| err.post_info);
+	let dispatch_result = result.map(|_| ()).map_err(|e| e.error);
+	Extra::post_dispatch(Some(pre), &info, &post_info, encoded.len(), &dispatch_result)?;
+
+	crate::Pallet::<T>::note_applied_extrinsic(&result, info);
+	Ok(dispatch_result)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System, Test};
+	use sp_runtime::transaction_validity::InvalidTransaction;
+
+	mod signed {
+		use super::*;
+		use crate::{
+			extensions::{
+				check_non_zero_sender::CheckNonZeroSender, check_nonce::CheckNonce,
+				check_weight::CheckWeight,
+			},
+			mock::RuntimeCall,
+		};
+
+		pub type Extra = (CheckNonZeroSender<Test>, CheckNonce<Test>);
+
+		pub fn extra(nonce: u64) -> Extra {
+			(CheckNonZeroSender::new(), CheckNonce::from(nonce))
+		}
+
+		pub fn weighed(nonce: u64) -> (CheckNonce<Test>, CheckWeight<Test>) {
+			(CheckNonce::from(nonce), CheckWeight::new())
+		}
+
+		pub fn remark() -> RuntimeCall {
+			RuntimeCall::System(crate::Call::remark_with_event { remark: b"signed".to_vec() })
+		}
+	}
+
+	#[test]
+	fn signed_extrinsics_run_the_signed_extensions() {
+		use signed::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			System::inc_providers(&1);
+			assert_eq!(sign_and_execute::<Test, Extra>(1, remark(), extra(0)), Ok(Ok(())));
+			assert_eq!(System::account_nonce(1), 1);
+			assert_eq!(System::extrinsic_index(), Some(1));
+			assert!(System::events().iter().any(|record| matches!(
+				record.event,
+				crate::mock::RuntimeEvent::System(crate::Event::ExtrinsicSuccess { .. })
+			)));
+
+			assert_eq!(
+				sign_and_execute::<Test, Extra>(1, remark(), extra(0)),
+				Err(InvalidTransaction::Stale.into())
+			);
+			assert_eq!(
+				sign_and_execute::<Test, Extra>(1, remark(), extra(5)),
+				Err(InvalidTransaction::Future.into())
+			);
+
+			// The benchmarked weight of a remark does not fit into the mock block.
+			assert_eq!(
+				sign_and_execute::<Test, _>(1, remark(), weighed(1)),
+				Err(InvalidTransaction::ExhaustsResources.into())
+			);
+		});
+	}
+
+	#[test]
+	fn signed_extrinsics_check_the_signature() {
+		use signed::*;
+
+		new_test_ext().execute_with(|| {
+			let extrinsic = sign_extrinsic::<Test, Extra>(1, remark(), extra(0)).unwrap();
+			assert_eq!(extrinsic.signature.as_ref().map(|(who, _, _)| *who), Some(1));
+			assert!(extrinsic.clone().check(&IdentityLookup::<u64>::default()).is_ok());
+
+			let mut forged = extrinsic;
+			forged.signature.as_mut().unwrap().0 = 2;
+			assert_eq!(
+				forged.check(&IdentityLookup::<u64>::default()).err(),
+				Some(InvalidTransaction::BadProof.into())
+			);
+		});
+	}
+
+	mod filtered {
+		use crate as frame_system;
+		use frame_support::{derive_impl, storage::unhashed, traits::Contains};
+
+		type Block = frame_system::mocking::MockBlock<Runtime>;
+
+		frame_support::construct_runtime!(
+			pub enum Runtime
+			{
+				System: frame_system,
+			}
+		);
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Runtime {
+			type BaseCallFilter = RecordingFilter;
+			type Block = Block;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = PalletInfo;
+		}
+
+		pub const REJECTED: &[u8] = b"rejected";
+
+		/// A call filter rejecting every call and counting the rejections, outside of the storage
+		/// layer of the dispatch.
+		pub struct RecordingFilter;
+
+		impl Contains<RuntimeCall> for RecordingFilter {
+			fn contains(_: &RuntimeCall) -> bool {
+				unhashed::put(REJECTED, &(unhashed::get_or_default::<u32>(REJECTED) + 1));
+				false
+			}
+		}
+
+		pub type Extra = crate::CheckNonZeroSender<Runtime>;
+
+		pub fn remark() -> RuntimeCall {
+			RuntimeCall::System(crate::Call::remark { remark: vec![] })
+		}
+
+		pub fn rejected() -> Option<u32> {
+			unhashed::get(REJECTED)
+		}
+
+		pub fn new_test_ext() -> sp_io::TestExternalities {
+			use sp_runtime::BuildStorage;
+
+			RuntimeGenesisConfig::default().build_storage().unwrap().into()
+		}
+	}
+
+	#[test]
+	#[should_panic(
+		expected = "but left 1 storage mutation(s) behind, is a storage layer missing? \
+		[0x72656a6563746564]"
+	)]
+	fn signed_extrinsics_leaving_mutations_behind_are_caught() {
+		use filtered::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let _ = sign_and_execute::<Runtime, Extra>(1, remark(), Extra::new());
+		});
+	}
+
+	#[test]
+	fn signed_extrinsics_can_skip_the_noop_check() {
+		use filtered::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let result = sign_and_execute_with::<Runtime, Extra>(false, 1, remark(), Extra::new());
+			assert_eq!(result, Ok(Err(crate::Error::<Runtime>::CallFiltered.into())));
+			assert_eq!(rejected(), Some(1));
+			assert!(System::events().iter().any(|record| matches!(
+				record.event,
+				RuntimeEvent::System(crate::Event::ExtrinsicFailed { .. })
+			)));
+		});
+	}
+}
@@ -0,0 +1,389 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Drive a migration running over several blocks, with faults injected between steps.
+
+use super::{check_invariants, count_db_ops, jump_to_block};
+use crate::Config;
+use codec::{Decode, DecodeAll, Encode};
+use frame_support::{
+	storage::unhashed,
+	traits::Get,
+	weights::{Weight, WeightMeter},
+};
+use sp_runtime::traits::UniqueSaturatedInto;
+use std::{collections::BTreeMap, fmt, marker::PhantomData};
+
+/// The error of a step of a [`MockSteppedMigration`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum SteppedMigrationError {
+	/// The step can't make progress within the allowance of a block.
+	InsufficientWeight {
+		/// The weight the step needs.
+		required: Weight,
+	},
+	/// The persisted cursor failed to decode.
+	InvalidCursor,
+	/// The migration failed.
+	Failed,
+}
+
+/// A migration executed in bounded steps over several blocks, see [`SteppedMigrationDriver`].
+pub trait MockSteppedMigration {
+	/// The progress of the migration, persisted between the steps.
+	type Cursor: Encode + Decode + fmt::Debug;
+
+	/// Execute a step from `cursor`, `None` for the first one, within the weight of `meter`.
+	///
+	/// Returns the cursor of the next step, or `None` once the migration is complete.
+	fn step(
+		cursor: Option<Self::Cursor>,
+		meter: &mut WeightMeter,
+	) -> Result<Option<Self::Cursor>, SteppedMigrationError>;
+}
+
+/// A step executed by [`SteppedMigrationDriver`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StepProgress {
+	/// The block the step was executed in.
+	pub block: u64,
+	/// The weight consumed from the meter.
+	pub consumed: Weight,
+	/// The weight of the storage accesses of the step.
+	pub measured: Weight,
+	/// The encoded cursor returned by the step, `None` once complete or if the step failed.
+	pub cursor: Option<Vec<u8>>,
+	/// The result of the step.
+	pub result: Result<(), SteppedMigrationError>,
+}
+
+/// A fault injected by [`SteppedMigrationDriver`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum StepFault {
+	Fail,
+	CorruptCursor,
+}
+
+/// Drives a [`MockSteppedMigration`] one step per block, like the scheduler of multi-block
+/// migrations.
+///
+/// Each step gets a [`WeightMeter`] limited to the allowance of a block. The cursor is persisted
+/// in the externalities between the steps, and must encode to the same bytes after decoding. A
+/// step must consume from its meter at least the weight of its storage accesses, as measured by
+/// [`count_db_ops`].
+pub struct SteppedMigrationDriver<T, M> {
+	allowance: Weight,
+	max_steps: u32,
+	faults: BTreeMap<u32, StepFault>,
+	progress: Vec<StepProgress>,
+	_phantom: PhantomData<(T, M)>,
+}
+
+impl<T: Config, M: MockSteppedMigration> SteppedMigrationDriver<T, M> {
+	/// Drive the migration `M` with an allowance of `allowance` per block.
+	pub fn new(allowance: Weight) -> Self {
+		Self {
+			allowance,
+			max_steps: 10_000,
+			faults: Default::default(),
+			progress: Default::default(),
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Fail if the migration isn't complete after `max_steps` steps, 10 000 by default.
+	pub fn max_steps(mut self, max_steps: u32) -> Self {
+		self.max_steps = max_steps;
+		self
+	}
+
+	/// Make the `step`th step, counting from 0, fail with [`SteppedMigrationError::Failed`]
+	/// instead of being executed.
+	pub fn fail_step(mut self, step: u32) -> Self {
+		self.faults.insert(step, StepFault::Fail);
+		self
+	}
+
+	/// Corrupt the persisted cursor before the `step`th step, counting from 0.
+	pub fn corrupt_cursor_before(mut self, step: u32) -> Self {
+		self.faults.insert(step, StepFault::CorruptCursor);
+		self
+	}
+
+	/// The storage key of the persisted cursor.
+	fn cursor_key() -> Vec<u8> {
+		let name = sp_io::hashing::twox_128(std::any::type_name::<M>().as_bytes());
+		[&b":mock_stepped_migration:"[..], &name[..]].concat()
+	}
+
+	/// The persisted cursor, `None` before the first step and once complete.
+	pub fn cursor(&self) -> Option<Result<M::Cursor, SteppedMigrationError>> {
+		unhashed::get_raw(&Self::cursor_key()).map(|cursor| {
+			M::Cursor::decode_all(&mut &cursor[..])
+				.map_err(|_| SteppedMigrationError::InvalidCursor)
+		})
+	}
+
+	/// Forget the persisted cursor, restarting the migration from the beginning.
+	pub fn clear_cursor(&self) {
+		unhashed::kill(&Self::cursor_key());
+	}
+
+	/// The steps executed so far.
+	pub fn progress(&self) -> &[StepProgress] {
+		&self.progress
+	}
+
+	/// Execute the steps of the migration, one per block, until it is complete or a step fails.
+	///
+	/// Returns the number of steps executed by this call. After a failure, calling it again
+	/// resumes from the persisted cursor.
+	///
+	/// # Panics
+	///
+	/// If a step exceeds the allowance, if the cursor doesn't encode to the same bytes after
+	/// decoding, or if the migration isn't complete after the maximum number of steps.
+	pub fn run(&mut self) -> Result<u32, SteppedMigrationError> {
+		let first = self.progress.len();
+		loop {
+			let step = self.progress.len() as u32;
+			assert!(step < self.max_steps, "the migration isn't complete after {} steps", step);
+			let now: u64 = crate::Pallet::<T>::block_number().unique_saturated_into();
+			let block = now + 1;
+			// The steps run in otherwise empty blocks, without any hooks.
+			jump_to_block::<T, ()>(block.unique_saturated_into());
+
+			let fault = self.faults.remove(&step);
+			if fault == Some(StepFault::CorruptCursor) {
+				unhashed::put_raw(&Self::cursor_key(), b"\xff\xff\xff\xff\xff");
+			}
+			let cursor = self.cursor().transpose();
+			let mut meter = WeightMeter::from_limit(self.allowance);
+			let (result, ops) = count_db_ops(|| match (fault, cursor) {
+				(Some(StepFault::Fail), _) => Err(SteppedMigrationError::Failed),
+				(_, Err(e)) => Err(e),
+				(_, Ok(cursor)) => M::step(cursor, &mut meter),
+			});
+			let measured = T::DbWeight::get().reads_writes(ops.reads.into(), ops.writes.into());
+			let (consumed, at) = (meter.consumed(), self.progress.len());
+			assert!(
+				consumed.all_lte(self.allowance) && measured.all_lte(self.allowance),
+				"step {} at block {} overshot the allowance of {:?}: consumed {:?}, measured {:?}",
+				at,
+				block,
+				self.allowance,
+				consumed,
+				measured,
+			);
+			assert!(
+				measured.all_lte(consumed),
+				"step {} at block {} consumed {:?}, less than its storage accesses {:?}",
+				at,
+				block,
+				consumed,
+				measured,
+			);
+
+			let cursor = result.as_ref().ok().and_then(Option::as_ref).map(|cursor| {
+				let encoded = cursor.encode();
+				let reencoded = M::Cursor::decode_all(&mut &encoded[..]).map(|c| c.encode());
+				assert_eq!(
+					reencoded.as_ref(),
+					Ok(&encoded),
+					"the cursor {:?} doesn't round trip",
+					cursor
+				);
+				encoded
+			});
+			match (&result, &cursor) {
+				(Ok(_), Some(cursor)) => unhashed::put_raw(&Self::cursor_key(), cursor),
+				(Ok(_), None) => self.clear_cursor(),
+				(Err(_), _) => {},
+			}
+
+			let done = matches!(result, Ok(None));
+			let result = result.map(|_| ());
+			self.progress.push(StepProgress {
+				block,
+				consumed,
+				measured,
+				cursor,
+				result: result.clone(),
+			});
+			check_invariants(&format!("after migration step {} at block {}", at, block));
+			result?;
+			if done {
+				return Ok((self.progress.len() - first) as u32)
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System, Test};
+
+	mod stepped {
+		use super::*;
+		use frame_support::Twox64Concat;
+
+		#[frame_support::storage_alias]
+		pub type Old = StorageMap<Toy, Twox64Concat, u32, u32>;
+
+		#[frame_support::storage_alias]
+		pub type New = StorageMap<Toy, Twox64Concat, u32, u64>;
+
+		pub fn seed(entries: u32) {
+			(0..entries).for_each(|i| Old::insert(i, i));
+		}
+
+		fn entry_weight() -> Weight {
+			<Test as Config>::DbWeight::get().reads_writes(1, 2)
+		}
+
+		/// Moves the entries of `Old` to `New` in order, doubling them.
+		pub struct Migration;
+
+		impl MockSteppedMigration for Migration {
+			type Cursor = u32;
+
+			fn step(
+				cursor: Option<u32>,
+				meter: &mut WeightMeter,
+			) -> Result<Option<u32>, SteppedMigrationError> {
+				let mut next = cursor.unwrap_or_default();
+				if !meter.can_consume(entry_weight()) {
+					return Err(SteppedMigrationError::InsufficientWeight {
+						required: entry_weight(),
+					})
+				}
+				while meter.try_consume(entry_weight()).is_ok() {
+					match Old::take(next) {
+						Some(value) => New::insert(next, u64::from(value) * 2),
+						None => return Ok(None),
+					}
+					next += 1;
+				}
+				Ok(Some(next))
+			}
+		}
+
+		/// Migrates 50 entries per step, whatever the meter says.
+		pub struct Greedy;
+
+		impl MockSteppedMigration for Greedy {
+			type Cursor = u32;
+
+			fn step(
+				cursor: Option<u32>,
+				meter: &mut WeightMeter,
+			) -> Result<Option<u32>, SteppedMigrationError> {
+				let start = cursor.unwrap_or_default();
+				for i in start..start + 50 {
+					let _ = meter.try_consume(entry_weight());
+					if let Some(value) = Old::take(i) {
+						New::insert(i, u64::from(value));
+					}
+				}
+				Ok(Some(start + 50))
+			}
+		}
+
+		/// An allowance of 10 entries per block.
+		pub fn allowance() -> Weight {
+			entry_weight() * 10
+		}
+	}
+
+	#[test]
+	fn stepped_migration_completes_across_blocks() {
+		use stepped::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			seed(1_000);
+			let mut driver = SteppedMigrationDriver::<Test, Migration>::new(allowance());
+			// 100 full steps, and one finding nothing left.
+			assert_eq!(driver.run(), Ok(101));
+
+			assert_eq!(Old::iter().count(), 0);
+			assert_eq!(New::iter().count(), 1_000);
+			assert_eq!(New::get(999), Some(1_998));
+			assert_eq!(System::block_number(), 102);
+			assert!(driver.cursor().is_none());
+
+			let progress = driver.progress();
+			assert_eq!(progress[0].block, 2);
+			assert_eq!(progress[0].cursor, Some(10u32.encode()));
+			assert_eq!(progress[0].consumed, allowance());
+			assert_eq!(progress[99].cursor, Some(1_000u32.encode()));
+			assert_eq!(progress[100].cursor, None);
+			assert!(progress.iter().all(|step| step.result.is_ok()));
+		});
+	}
+
+	#[test]
+	fn stepped_migration_recovers_from_faults() {
+		use stepped::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			seed(30);
+			let mut driver = SteppedMigrationDriver::<Test, Migration>::new(allowance())
+				.fail_step(1)
+				.corrupt_cursor_before(3);
+
+			assert_eq!(driver.run(), Err(SteppedMigrationError::Failed));
+			// The failed step didn't lose the cursor.
+			assert_eq!(driver.cursor(), Some(Ok(10)));
+			assert_eq!(driver.run(), Err(SteppedMigrationError::InvalidCursor));
+			assert_eq!(driver.progress().len(), 4);
+			assert_eq!(driver.cursor(), Some(Err(SteppedMigrationError::InvalidCursor)));
+
+			// Restarting from the beginning stops at the first migrated entry, leaving the
+			// others behind: this migration doesn't recover from a lost cursor.
+			driver.clear_cursor();
+			assert_eq!(driver.run(), Ok(1));
+			assert_eq!(New::iter().count(), 20);
+			assert_eq!(Old::iter().count(), 10);
+		});
+	}
+
+	#[test]
+	fn stepped_migration_without_progress_fails() {
+		use stepped::*;
+
+		new_test_ext().execute_with(|| {
+			seed(1);
+			let mut driver = SteppedMigrationDriver::<Test, Migration>::new(Weight::zero());
+			assert!(matches!(driver.run(), Err(SteppedMigrationError::InsufficientWeight { .. })));
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "step 0 at block 2 overshot the allowance")]
+	fn greedy_step_is_detected() {
+		use stepped::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			seed(1_000);
+			let _ = SteppedMigrationDriver::<Test, Greedy>::new(allowance()).run();
+		});
+	}
+}
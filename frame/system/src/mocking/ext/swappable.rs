@@ -0,0 +1,352 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Implementations of a trait that can be swapped per test.
+
+use super::with_extension;
+use sp_externalities::{Extension, ExternalitiesExt};
+use std::{
+	any::{Any, TypeId},
+	collections::BTreeMap,
+	marker::PhantomData,
+	sync::Arc,
+};
+
+/// The implementations registered with [`SwappableImpl::set`] in the externalities.
+#[derive(Default)]
+struct SwappableImpls(BTreeMap<TypeId, Arc<dyn Any + Send + Sync>>);
+
+impl Extension for SwappableImpls {
+	fn as_mut_any(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+/// A mock `Config` type forwarding to an implementation chosen by each test.
+///
+/// Handler traits of a `Config` (e.g. a price oracle) usually only have associated functions, so
+/// [`swappable_impl!`](crate::swappable_impl) generates an object safe mirror of the trait and
+/// implements the original trait for `SwappableImpl<dyn Mirror>` by forwarding to the registered
+/// implementation:
+///
+/// ```
+/// pub trait PriceOracle {
+///     fn price(asset: u32) -> u64;
+/// }
+///
+/// frame_system::swappable_impl!(PriceOracle => DynPriceOracle {
+///     fn price(asset: u32) -> u64;
+/// });
+///
+/// struct FixedPrice(u64);
+/// impl DynPriceOracle for FixedPrice {
+///     fn price(&self, _asset: u32) -> u64 {
+///         self.0
+///     }
+/// }
+///
+/// // Used as `type PriceOracle = SwappableImpl<dyn DynPriceOracle>;` in the mock runtime.
+/// type Oracle = frame_system::mocking::SwappableImpl<dyn DynPriceOracle>;
+///
+/// sp_io::TestExternalities::default().execute_with(|| {
+///     Oracle::set(Box::new(FixedPrice(5)));
+///     assert_eq!(<Oracle as PriceOracle>::price(1), 5);
+/// });
+/// ```
+///
+/// The implementation is registered in the externalities, so it is dropped with them and never
+/// observed by the next test.
+pub struct SwappableImpl<Trait: ?Sized>(PhantomData<Trait>);
+
+impl<Trait: ?Sized + Send + Sync + 'static> SwappableImpl<Trait> {
+	/// Forward all calls to `implementation` from now on.
+	///
+	/// # Panics
+	///
+	/// Outside of externalities.
+	pub fn set(implementation: Box<Trait>) {
+		with_extension("swappable implementations", |SwappableImpls(impls)| {
+			impls.insert(TypeId::of::<Trait>(), Arc::new(implementation));
+		});
+	}
+
+	/// Remove the registered implementation.
+	pub fn clear() {
+		sp_externalities::with_externalities(|mut ext| {
+			if let Some(SwappableImpls(impls)) = ext.extension::<SwappableImpls>() {
+				impls.remove(&TypeId::of::<Trait>());
+			}
+		});
+	}
+
+	/// The implementation registered in the current externalities, if any.
+	pub fn get() -> Option<Arc<Box<Trait>>> {
+		sp_externalities::with_externalities(|mut ext| {
+			ext.extension::<SwappableImpls>()
+				.and_then(|SwappableImpls(impls)| impls.get(&TypeId::of::<Trait>()).cloned())
+		})
+		.flatten()
+		.map(|implementation| {
+			implementation.downcast().expect("entries are keyed by their type; qed")
+		})
+	}
+
+	/// The registered implementation.
+	///
+	/// # Panics
+	///
+	/// If nothing is registered, naming `trait_name` in the message.
+	pub fn expect(trait_name: &str) -> Arc<Box<Trait>> {
+		Self::get().unwrap_or_else(|| {
+			panic!(
+				"`{}` was called, but no implementation is registered with `SwappableImpl::set` \
+				 and there is no default",
+				trait_name,
+			)
+		})
+	}
+}
+
+/// Generate an object safe mirror of a trait and implement the trait for
+/// [`SwappableImpl`](crate::mocking::SwappableImpl) of the mirror.
+///
+/// Every associated function of the original trait is listed with its signature and becomes a
+/// `&self` method of the mirror. Optionally, `default: <expr>` gives an implementation of the
+/// mirror used when a test didn't register one; without it such calls panic.
+#[macro_export]
+macro_rules! swappable_impl {
+	(
+		$trait:path => $mirror:ident {
+			$( fn $fn:ident ( $( $arg:ident : $arg_ty:ty ),* $(,)? ) $( -> $ret:ty )?; )*
+		}, default: $default:expr $(,)?
+	) => {
+		$crate::swappable_impl!(@impl $trait => $mirror {
+			$( fn $fn( $( $arg: $arg_ty ),* ) $( -> $ret )?; )*
+		} {
+			let default: ::std::boxed::Box<dyn $mirror> = ::std::boxed::Box::new($default);
+			Some(::std::sync::Arc::new(default))
+		});
+	};
+	(
+		$trait:path => $mirror:ident {
+			$( fn $fn:ident ( $( $arg:ident : $arg_ty:ty ),* $(,)? ) $( -> $ret:ty )?; )*
+		} $(,)?
+	) => {
+		$crate::swappable_impl!(@impl $trait => $mirror {
+			$( fn $fn( $( $arg: $arg_ty ),* ) $( -> $ret )?; )*
+		} { None });
+	};
+	(@impl $trait:path => $mirror:ident {
+		$( fn $fn:ident ( $( $arg:ident : $arg_ty:ty ),* ) $( -> $ret:ty )?; )*
+	} $default:block) => {
+		pub trait $mirror: Send + Sync + 'static {
+			$( fn $fn(&self, $( $arg: $arg_ty ),* ) $( -> $ret )?; )*
+		}
+
+		impl $trait for $crate::mocking::SwappableImpl<dyn $mirror> {
+			$(
+				fn $fn( $( $arg: $arg_ty ),* ) $( -> $ret )? {
+					Self::get()
+						.or_else(|| $default)
+						.unwrap_or_else(|| Self::expect(::core::stringify!($trait)))
+						.$fn( $( $arg ),* )
+				}
+			)*
+		}
+	};
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	mod swappable {
+		use super::*;
+		use crate as frame_system;
+		use frame_support::derive_impl;
+		use sp_runtime::BuildStorage;
+
+		pub trait PriceOracle {
+			fn price(asset: u32) -> u64;
+		}
+
+		pub trait Treasury {
+			/// Deposit `amount`, returning the part that was accepted.
+			fn deposit(amount: u64) -> u64;
+		}
+
+		crate::swappable_impl!(PriceOracle => DynPriceOracle {
+			fn price(asset: u32) -> u64;
+		});
+
+		crate::swappable_impl!(Treasury => DynTreasury {
+			fn deposit(amount: u64) -> u64;
+		}, default: ());
+
+		/// Accepts every deposit.
+		impl DynTreasury for () {
+			fn deposit(&self, amount: u64) -> u64 {
+				amount
+			}
+		}
+
+		/// Accepts at most the given amount.
+		pub struct CappedTreasury(pub u64);
+		impl DynTreasury for CappedTreasury {
+			fn deposit(&self, amount: u64) -> u64 {
+				amount.min(self.0)
+			}
+		}
+
+		pub struct FixedPrice(pub u64);
+		impl DynPriceOracle for FixedPrice {
+			fn price(&self, _asset: u32) -> u64 {
+				self.0
+			}
+		}
+
+		pub struct PricePerAsset;
+		impl DynPriceOracle for PricePerAsset {
+			fn price(&self, asset: u32) -> u64 {
+				asset as u64 * 100
+			}
+		}
+
+		// A toy pallet quoting prices with the `PriceOracle` of its `Config`.
+		#[frame_support::pallet]
+		pub mod toy {
+			use super::{frame_system, PriceOracle};
+			use frame_support::pallet_prelude::*;
+			use frame_system::pallet_prelude::*;
+
+			#[pallet::pallet]
+			pub struct Pallet<T>(_);
+
+			#[pallet::config]
+			pub trait Config: frame_system::Config {
+				type PriceOracle: PriceOracle;
+			}
+
+			#[pallet::storage]
+			pub type LastQuote<T> = StorageValue<_, u64>;
+
+			#[pallet::call]
+			impl<T: Config> Pallet<T> {
+				#[pallet::call_index(0)]
+				#[pallet::weight(Weight::zero())]
+				pub fn quote(_: OriginFor<T>, asset: u32, amount: u64) -> DispatchResult {
+					LastQuote::<T>::put(T::PriceOracle::price(asset) * amount);
+					Ok(())
+				}
+			}
+		}
+
+		type Block = frame_system::mocking::MockBlock<Test>;
+
+		frame_support::construct_runtime!(
+			pub enum Test
+			{
+				System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+				Toy: toy,
+			}
+		);
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Test {
+			type Block = Block;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = PalletInfo;
+		}
+
+		impl toy::Config for Test {
+			type PriceOracle = SwappableImpl<dyn DynPriceOracle>;
+		}
+
+		pub fn new_test_ext() -> sp_io::TestExternalities {
+			RuntimeGenesisConfig::default().build_storage().unwrap().into()
+		}
+
+		/// Quote `amount` of `asset` with the toy pallet.
+		pub fn quote(asset: u32, amount: u64) -> Option<u64> {
+			Toy::quote(RuntimeOrigin::signed(1), asset, amount).unwrap();
+			toy::LastQuote::<Test>::get()
+		}
+	}
+
+	#[test]
+	fn swappable_impl_uses_registered_implementation() {
+		use swappable::*;
+
+		new_test_ext().execute_with(|| {
+			SwappableImpl::<dyn DynPriceOracle>::set(Box::new(FixedPrice(5)));
+			assert_eq!(quote(1, 2), Some(10));
+			assert_eq!(quote(3, 2), Some(10));
+		});
+	}
+
+	#[test]
+	fn swappable_impl_can_be_swapped_per_test() {
+		use swappable::*;
+
+		new_test_ext().execute_with(|| {
+			SwappableImpl::<dyn DynPriceOracle>::set(Box::new(PricePerAsset));
+			assert_eq!(quote(3, 2), Some(600));
+
+			SwappableImpl::<dyn DynPriceOracle>::set(Box::new(FixedPrice(1)));
+			assert_eq!(quote(3, 2), Some(2));
+		});
+	}
+
+	#[test]
+	fn swappable_impl_is_dropped_with_the_externalities() {
+		use swappable::*;
+
+		new_test_ext().execute_with(|| {
+			SwappableImpl::<dyn DynPriceOracle>::set(Box::new(FixedPrice(5)));
+			assert!(SwappableImpl::<dyn DynPriceOracle>::get().is_some());
+		});
+		new_test_ext().execute_with(|| {
+			assert!(SwappableImpl::<dyn DynPriceOracle>::get().is_none());
+		});
+	}
+
+	#[test]
+	fn swappable_impl_falls_back_to_default() {
+		use swappable::*;
+		type Treasury = SwappableImpl<dyn DynTreasury>;
+
+		new_test_ext().execute_with(|| {
+			assert_eq!(<Treasury as swappable::Treasury>::deposit(10), 10);
+
+			Treasury::set(Box::new(CappedTreasury(4)));
+			assert_eq!(<Treasury as swappable::Treasury>::deposit(10), 4);
+
+			Treasury::clear();
+			assert_eq!(<Treasury as swappable::Treasury>::deposit(10), 10);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "`PriceOracle` was called, but no implementation is registered")]
+	fn swappable_impl_without_default_panics() {
+		use swappable::*;
+
+		new_test_ext().execute_with(|| quote(1, 1));
+	}
+}
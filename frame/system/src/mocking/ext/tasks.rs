@@ -0,0 +1,337 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A mock task queue, serviced under a weight limit.
+
+use super::{check_invariants, with_extension};
+use codec::{Decode, Encode};
+use frame_support::{
+	traits::ServiceQueues,
+	weights::{Weight, WeightMeter},
+};
+use sp_externalities::Extension;
+use sp_runtime::{DispatchError, DispatchResult};
+use std::{
+	any::Any,
+	collections::{BTreeMap, VecDeque},
+	marker::PhantomData,
+};
+
+/// A task that can be executed by a [`MockTaskRunner`].
+pub trait MockTask: Encode + Decode + 'static {
+	/// A short name identifying the kind of task.
+	fn name(&self) -> &'static str;
+
+	/// The weight the task is estimated to take.
+	fn weight(&self) -> Weight;
+
+	/// Execute the task.
+	fn run(self) -> DispatchResult;
+}
+
+/// A task that was scheduled with a [`MockTaskRunner`].
+#[derive(Clone, Eq, PartialEq, Debug, Encode, Decode)]
+pub struct ScheduledTask {
+	/// The name of the task, see [`MockTask::name`].
+	pub name: String,
+	/// The encoded task.
+	pub params: Vec<u8>,
+	/// The weight estimate of the task, see [`MockTask::weight`].
+	pub weight: Weight,
+}
+
+/// The queue of one [`MockTask`] type.
+#[derive(Default)]
+struct TaskQueue {
+	/// The encoded tasks waiting for execution, with their name.
+	pending: VecDeque<(&'static str, Vec<u8>)>,
+	/// All tasks scheduled so far.
+	history: Vec<ScheduledTask>,
+	/// The failures injected with [`MockTaskRunner::fail_next`].
+	failures: VecDeque<DispatchError>,
+	/// Whether tasks may be left pending, see [`MockTaskRunner::allow_pending`].
+	allow_pending: bool,
+}
+
+/// The task queues of the externalities, keyed by the name of the task type.
+///
+/// Checks when the externalities are dropped that no task was left pending.
+#[derive(Default)]
+struct MockTasks(BTreeMap<&'static str, TaskQueue>);
+
+impl Extension for MockTasks {
+	fn as_mut_any(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+impl Drop for MockTasks {
+	fn drop(&mut self) {
+		// Don't hide the original failure of the test.
+		if std::thread::panicking() {
+			return
+		}
+
+		for queue in self.0.values().filter(|queue| !queue.allow_pending) {
+			if !queue.pending.is_empty() {
+				let names = queue.pending.iter().map(|(name, _)| name).collect::<Vec<_>>();
+				panic!(
+					"{} task(s) left unexecuted: {:?}; run them or call `MockTaskRunner::allow_pending`",
+					names.len(),
+					names,
+				);
+			}
+		}
+	}
+}
+
+/// Executes [`MockTask`]s deterministically, under the control of the test.
+///
+/// Tasks are queued in the externalities with [`Self::schedule`] and only executed by one of the
+/// drivers ([`Self::run_next`], [`Self::run_all_within`] or [`ServiceQueues::service_queues`]).
+/// The queue, including the failures injected with [`Self::fail_next`], is dropped with the
+/// externalities, which panic at that point if a scheduled task was silently left pending.
+pub struct MockTaskRunner<Task>(PhantomData<Task>);
+
+impl<Task: MockTask> MockTaskRunner<Task> {
+	/// Access the queue of `Task` in the current externalities.
+	///
+	/// # Panics
+	///
+	/// Outside of externalities.
+	fn with_queue<R>(f: impl FnOnce(&mut TaskQueue) -> R) -> R {
+		with_extension("mock tasks", |MockTasks(queues)| {
+			f(queues.entry(std::any::type_name::<Task>()).or_default())
+		})
+	}
+
+	fn decode(task: &[u8]) -> Task {
+		Task::decode(&mut &task[..]).expect("scheduled tasks are encoded; qed")
+	}
+
+	/// Schedule `task` for execution.
+	pub fn schedule(task: Task) {
+		let scheduled = ScheduledTask {
+			name: task.name().into(),
+			params: task.encode(),
+			weight: task.weight(),
+		};
+		Self::with_queue(|queue| {
+			queue.pending.push_back((task.name(), task.encode()));
+			queue.history.push(scheduled);
+		});
+	}
+
+	/// All tasks scheduled so far, including the ones already executed or dropped.
+	pub fn scheduled() -> Vec<ScheduledTask> {
+		Self::with_queue(|queue| queue.history.clone())
+	}
+
+	/// The tasks waiting for execution, in the order they will be executed.
+	pub fn pending() -> Vec<Task> {
+		Self::with_queue(|queue| queue.pending.iter().map(|(_, task)| Self::decode(task)).collect())
+	}
+
+	/// Remove the next pending task without executing it.
+	pub fn drop_next() -> Option<Task> {
+		Self::with_queue(|queue| queue.pending.pop_front()).map(|(_, task)| Self::decode(&task))
+	}
+
+	/// Make the next executed task fail with `error`, without running it.
+	///
+	/// Can be called multiple times to fail several tasks in a row.
+	pub fn fail_next(error: DispatchError) {
+		Self::with_queue(|queue| queue.failures.push_back(error));
+	}
+
+	/// Execute the next pending task, regardless of its weight.
+	///
+	/// Returns `None` if no task is pending.
+	pub fn run_next() -> Option<DispatchResult> {
+		let task = Self::drop_next()?;
+		let failure = Self::with_queue(|queue| queue.failures.pop_front());
+
+		let boundary = format!("after task `{}`", task.name());
+		let result = match failure {
+			Some(error) => Err(error),
+			None => task.run(),
+		};
+		check_invariants(&boundary);
+		Some(result)
+	}
+
+	/// Execute pending tasks in order, as long as their weight estimate fits into `limit`.
+	///
+	/// Stops at the first task that doesn't fit and returns the weight consumed.
+	pub fn run_all_within(limit: Weight) -> Weight {
+		let mut meter = WeightMeter::from_limit(limit);
+		while let Some(task) = Self::pending().first() {
+			if meter.try_consume(task.weight()).is_err() {
+				break
+			}
+			Self::run_next();
+		}
+
+		meter.consumed()
+	}
+
+	/// Don't panic for tasks that are still pending when the externalities are dropped.
+	pub fn allow_pending() {
+		Self::with_queue(|queue| queue.allow_pending = true);
+	}
+}
+
+impl<Task: MockTask> ServiceQueues for MockTaskRunner<Task> {
+	type OverweightMessageAddress = ();
+
+	fn service_queues(weight_limit: Weight) -> Weight {
+		Self::run_all_within(weight_limit)
+	}
+}
+
+/// Assert that a task matching `matcher` was scheduled with the [`MockTaskRunner`] of `Task`.
+#[track_caller]
+pub fn assert_task_scheduled<Task: MockTask>(matcher: impl Fn(&Task) -> bool) {
+	let scheduled = MockTaskRunner::<Task>::scheduled();
+	let found = scheduled.iter().any(
+		|scheduled| matches!(Task::decode(&mut &scheduled.params[..]), Ok(task) if matcher(&task)),
+	);
+	assert!(found, "no matching task was scheduled, scheduled tasks: {:?}", scheduled);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::new_test_ext;
+
+	mod tasks {
+		use super::*;
+		use frame_support::storage::unhashed;
+
+		/// A toy pallet scheduling a task to credit an account.
+		pub fn deposit(who: u64, amount: u64) {
+			MockTaskRunner::<Credit>::schedule(Credit { who, amount });
+		}
+
+		pub fn balance(who: u64) -> u64 {
+			unhashed::get_or_default(&who.encode())
+		}
+
+		#[derive(Encode, Decode, Debug, PartialEq)]
+		pub struct Credit {
+			pub who: u64,
+			pub amount: u64,
+		}
+
+		impl MockTask for Credit {
+			fn name(&self) -> &'static str {
+				"credit"
+			}
+
+			fn weight(&self) -> Weight {
+				Weight::from_parts(self.amount, 0)
+			}
+
+			fn run(self) -> DispatchResult {
+				unhashed::put(&self.who.encode(), &(balance(self.who) + self.amount));
+				Ok(())
+			}
+		}
+	}
+
+	#[test]
+	fn mock_task_runner_executes_under_weight_cap() {
+		use tasks::*;
+		type Runner = MockTaskRunner<Credit>;
+
+		new_test_ext().execute_with(|| {
+			deposit(1, 10);
+			deposit(2, 20);
+			deposit(1, 30);
+			assert_task_scheduled::<Credit>(|task| task.who == 2);
+			assert_eq!(Runner::scheduled()[0].name, "credit");
+			assert_eq!(balance(1), 0);
+
+			// The second task doesn't fit next to the first one.
+			assert_eq!(
+				Runner::run_all_within(Weight::from_parts(25, 0)),
+				Weight::from_parts(10, 0)
+			);
+			assert_eq!(balance(1), 10);
+			assert_eq!(Runner::pending().len(), 2);
+
+			assert_eq!(Runner::service_queues(Weight::MAX), Weight::from_parts(50, 0));
+			assert_eq!((balance(1), balance(2)), (40, 20));
+			assert_eq!(Runner::run_next(), None);
+		});
+	}
+
+	#[test]
+	fn mock_task_runner_drops_and_fails_tasks() {
+		use tasks::*;
+		type Runner = MockTaskRunner<Credit>;
+
+		new_test_ext().execute_with(|| {
+			deposit(1, 10);
+			deposit(1, 20);
+			deposit(1, 30);
+
+			assert_eq!(Runner::drop_next(), Some(Credit { who: 1, amount: 10 }));
+			Runner::fail_next(DispatchError::Other("injected"));
+			assert_eq!(Runner::run_next(), Some(Err(DispatchError::Other("injected"))));
+			assert_eq!(Runner::run_next(), Some(Ok(())));
+			assert_eq!(balance(1), 30);
+			assert_eq!(Runner::scheduled().len(), 3);
+		});
+	}
+
+	#[test]
+	fn mock_task_runner_failures_do_not_outlive_the_externalities() {
+		use tasks::*;
+		type Runner = MockTaskRunner<Credit>;
+
+		new_test_ext().execute_with(|| Runner::fail_next(DispatchError::Other("injected")));
+
+		new_test_ext().execute_with(|| {
+			deposit(1, 10);
+			assert_eq!(Runner::run_next(), Some(Ok(())));
+			assert_eq!(balance(1), 10);
+		});
+	}
+
+	#[test]
+	#[should_panic(expected = "1 task(s) left unexecuted: [\"credit\"]")]
+	fn mock_task_runner_detects_pending_tasks() {
+		use tasks::*;
+
+		new_test_ext().execute_with(|| {
+			deposit(1, 10);
+			deposit(1, 20);
+			MockTaskRunner::<Credit>::run_next();
+		});
+	}
+
+	#[test]
+	fn mock_task_runner_allows_pending_tasks() {
+		use tasks::*;
+
+		new_test_ext().execute_with(|| {
+			deposit(1, 10);
+			MockTaskRunner::<Credit>::allow_pending();
+		});
+	}
+}
@@ -0,0 +1,499 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Mock time, and jump many blocks ahead without executing all of them.
+
+use super::{check_invariants, storage_pairs, with_extension};
+use crate::{pallet_prelude::BlockNumberFor, Config};
+use frame_support::traits::{Get, OnFinalize, OnInitialize, PalletsInfoAccess, Time, UnixTime};
+use sp_externalities::Extension;
+use sp_runtime::traits::{Hash, UniqueSaturatedFrom, UniqueSaturatedInto};
+use std::{
+	any::Any,
+	cell::RefCell,
+	collections::{BTreeMap, BTreeSet},
+	marker::PhantomData,
+};
+
+/// The current time of [`MockTime`] in the externalities, in milliseconds.
+#[derive(Default)]
+struct MockNow(u64);
+
+impl Extension for MockNow {
+	fn as_mut_any(&mut self) -> &mut dyn Any {
+		self
+	}
+}
+
+/// A clock for mock runtimes, advanced by [`TimeTravel`].
+///
+/// The time is kept in the externalities and starts at zero in each of them.
+pub struct MockTime;
+
+impl MockTime {
+	/// Set the current time, in milliseconds.
+	///
+	/// # Panics
+	///
+	/// Outside of externalities.
+	pub fn set(now: u64) {
+		with_extension("mock time", |MockNow(n)| *n = now);
+	}
+
+	/// The current time, in milliseconds.
+	///
+	/// # Panics
+	///
+	/// Outside of externalities.
+	pub fn get() -> u64 {
+		with_extension("mock time", |MockNow(n)| *n)
+	}
+}
+
+impl Time for MockTime {
+	type Moment = u64;
+
+	fn now() -> u64 {
+		Self::get()
+	}
+}
+
+impl UnixTime for MockTime {
+	fn now() -> std::time::Duration {
+		std::time::Duration::from_millis(Self::get())
+	}
+}
+
+/// Advances the block number of a mock runtime in one step, without executing every block.
+///
+/// Only the hooks of the pallets declared with [`Self::with_hooks_of`] are executed, at the
+/// target block, at the era boundaries crossed by [`Self::jump_eras`] and every
+/// [`Self::replay_every`] blocks. Each of these blocks is initialized and finalized by the hooks,
+/// without the rest of the block execution.
+///
+/// Before each jump, the `on_initialize` hooks of all pallets `All`, e.g.
+/// `AllPalletsWithoutSystem`, are executed once and rolled back. A warning is logged for every
+/// pallet which is not declared but writes to storage there, since its per-block state won't be
+/// maintained, see [`Self::undeclared_hooks`].
+///
+/// As a fidelity trade-off, the hashes of skipped blocks are only recorded in
+/// [`BlockHash`](crate::BlockHash) for every [`Self::sample_hashes`]th block and the last few
+/// blocks before the target.
+pub struct TimeTravel<T, All, Hooks = ()> {
+	sample_every: u64,
+	keep_last: u64,
+	replay_every: Option<u64>,
+	block_time: u64,
+	undeclared: RefCell<BTreeSet<&'static str>>,
+	_phantom: PhantomData<(T, All, Hooks)>,
+}
+
+impl<T: Config, All> Default for TimeTravel<T, All> {
+	fn default() -> Self {
+		Self {
+			sample_every: 100,
+			keep_last: 10,
+			replay_every: None,
+			block_time: 6_000,
+			undeclared: Default::default(),
+			_phantom: PhantomData,
+		}
+	}
+}
+
+impl<T: Config, All> TimeTravel<T, All> {
+	/// Create a time travel without hooks, recording the hash of every 100th and of the last 10
+	/// blocks and advancing [`MockTime`] by 6 seconds per block.
+	pub fn new() -> Self {
+		Self::default()
+	}
+}
+
+impl<T, All, Hooks> TimeTravel<T, All, Hooks>
+where
+	T: Config,
+	All: OnInitialize<BlockNumberFor<T>> + PalletsInfoAccess,
+	Hooks: OnInitialize<BlockNumberFor<T>> + OnFinalize<BlockNumberFor<T>> + PalletsInfoAccess,
+{
+	/// Execute the hooks of the pallets `H`, a tuple like `(Staking, Session)`, at the replayed
+	/// blocks.
+	pub fn with_hooks_of<H>(self) -> TimeTravel<T, All, H> {
+		TimeTravel {
+			sample_every: self.sample_every,
+			keep_last: self.keep_last,
+			replay_every: self.replay_every,
+			block_time: self.block_time,
+			undeclared: Default::default(),
+			_phantom: PhantomData,
+		}
+	}
+
+	/// Record the hash of every `every`th block and of the last `keep_last` blocks.
+	pub fn sample_hashes(mut self, every: u32, keep_last: u32) -> Self {
+		self.sample_every = every.max(1).into();
+		self.keep_last = keep_last.into();
+		self
+	}
+
+	/// Also execute the hooks every `every` blocks.
+	pub fn replay_every(mut self, every: u32) -> Self {
+		self.replay_every = Some(every.max(1).into());
+		self
+	}
+
+	/// Advance [`MockTime`] by `millis` per block.
+	pub fn block_time(mut self, millis: u64) -> Self {
+		self.block_time = millis;
+		self
+	}
+
+	/// The pallets of `All` found so far to write to storage in `on_initialize` before a jump,
+	/// but which are not part of the hooks.
+	pub fn undeclared_hooks(&self) -> Vec<&'static str> {
+		self.undeclared.borrow().iter().copied().collect()
+	}
+
+	/// Execute the `on_initialize` hooks of `All` at `block` and roll them back, logging a
+	/// warning for each undeclared pallet writing to storage, once per pallet.
+	fn calibrate(&self, block: u64) {
+		let before = storage_pairs().into_iter().collect::<BTreeMap<_, _>>();
+		sp_io::storage::start_transaction();
+		All::on_initialize(block.unique_saturated_into());
+		let after = storage_pairs();
+		let deleted = before.keys().filter(|key| !after.iter().any(|(k, _)| k == *key)).cloned();
+		let touched = after
+			.iter()
+			.filter(|(key, value)| before.get(key) != Some(value))
+			.map(|(key, _)| key.clone())
+			.chain(deleted)
+			.collect::<Vec<_>>();
+		sp_io::storage::rollback_transaction();
+
+		let declared = Hooks::infos();
+		let undeclared = All::infos()
+			.into_iter()
+			.filter(|info| !declared.iter().any(|declared| declared.name == info.name))
+			.filter(|info| {
+				let prefix = sp_io::hashing::twox_128(info.name.as_bytes());
+				touched.iter().any(|key| key.starts_with(&prefix))
+			})
+			.map(|info| info.name)
+			.collect::<Vec<_>>();
+		for name in undeclared {
+			if !self.undeclared.borrow_mut().insert(name) {
+				continue
+			}
+			log::warn!(
+				target: crate::LOG_TARGET,
+				"`{}` writes to storage in `on_initialize`, but its hooks are not replayed by \
+				`TimeTravel`; declare it with `with_hooks_of` if its per-block state matters",
+				name,
+			);
+		}
+	}
+
+	fn replay(&self, block: u64) {
+		let number: BlockNumberFor<T> = block.unique_saturated_into();
+		crate::Pallet::<T>::set_block_number(number);
+		Hooks::on_initialize(number);
+		Hooks::on_finalize(number);
+		check_invariants(&format!("after block {}", block));
+	}
+
+	fn record_hash(block: u64) {
+		let hash = T::Hashing::hash_of(&(b"time_travel", block));
+		crate::BlockHash::<T>::insert(BlockNumberFor::<T>::unique_saturated_from(block), hash);
+	}
+
+	fn jump(&self, to: u64, boundaries: impl Iterator<Item = u64>) {
+		let from: u64 = crate::Pallet::<T>::block_number().unique_saturated_into();
+		if to <= from {
+			return
+		}
+		self.calibrate(from + 1);
+
+		// The blocks whose hooks are replayed, excluding the target which is handled last.
+		let mut replayed =
+			boundaries.filter(|block| *block > from && *block < to).collect::<BTreeSet<_>>();
+		if let Some(every) = self.replay_every {
+			replayed
+				.extend((from / every + 1..).map(|i| i * every).take_while(|block| *block < to));
+		}
+
+		let mut sampled = (from / self.sample_every + 1..)
+			.map(|i| i * self.sample_every)
+			.take_while(|block| *block < to)
+			.collect::<BTreeSet<_>>();
+		// The hash of the current block, if already known, is never replaced by a fake one.
+		sampled.extend(to.saturating_sub(self.keep_last).max(from + 1)..to);
+
+		let now = MockTime::get();
+		for block in replayed.into_iter().chain(std::iter::once(to)) {
+			for block in sampled.range(..block).copied().collect::<Vec<_>>() {
+				Self::record_hash(block);
+				sampled.remove(&block);
+			}
+			MockTime::set(now + (block - from) * self.block_time);
+			if block == to {
+				let parent = BlockNumberFor::<T>::unique_saturated_from(to - 1);
+				let parent_hash = crate::BlockHash::<T>::try_get(parent)
+					.unwrap_or_else(|_| T::Hashing::hash_of(&(b"time_travel", to - 1)));
+				crate::ParentHash::<T>::put(parent_hash);
+			}
+			self.replay(block);
+		}
+	}
+
+	/// Jump to block `n`, initializing and finalizing it with the hooks.
+	///
+	/// Does nothing if the current block is `n` or later.
+	pub fn jump_to_block(&self, n: BlockNumberFor<T>) {
+		self.jump(n.unique_saturated_into(), std::iter::empty());
+	}
+
+	/// Jump to the start of the `count`th next era, with eras of `EraLength` blocks.
+	///
+	/// The hooks are executed at the start of each era crossed.
+	pub fn jump_eras<EraLength: Get<BlockNumberFor<T>>>(&self, count: u32) {
+		let length: u64 = EraLength::get().unique_saturated_into();
+		let length = length.max(1);
+		let from: u64 = crate::Pallet::<T>::block_number().unique_saturated_into();
+		let first = from / length + 1;
+		let boundaries = (first..first + u64::from(count)).map(|era| era * length);
+		let to = if count == 0 { from } else { (first + u64::from(count) - 1) * length };
+		self.jump(to, boundaries);
+	}
+}
+
+/// Jump to block `n` without executing any hooks of the pallets `All`, see
+/// [`TimeTravel::jump_to_block`].
+pub fn jump_to_block<T, All>(n: BlockNumberFor<T>)
+where
+	T: Config,
+	All: OnInitialize<BlockNumberFor<T>> + PalletsInfoAccess,
+{
+	TimeTravel::<T, All>::new().jump_to_block(n)
+}
+
+/// Jump `count` eras of `EraLength` blocks ahead without executing any hooks of the pallets
+/// `All`, see [`TimeTravel::jump_eras`].
+pub fn jump_eras<T, All, EraLength>(count: u32)
+where
+	T: Config,
+	All: OnInitialize<BlockNumberFor<T>> + PalletsInfoAccess,
+	EraLength: Get<BlockNumberFor<T>>,
+{
+	TimeTravel::<T, All>::new().jump_eras::<EraLength>(count)
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, System, Test};
+	use frame_support::storage::unhashed;
+
+	pub mod time_travel {
+		use super::*;
+		use frame_support::{parameter_types, traits::PalletInfoData, weights::Weight};
+
+		parameter_types! {
+			pub const EraLength: u64 = 14_400;
+		}
+
+		pub const BONDING_DURATION: u32 = 28;
+
+		const ERA: &[u8] = b"era";
+		const UNLOCKING: &[u8] = b"unlocking";
+		const SESSION: &[u8] = b"Session";
+
+		fn info(name: &'static str) -> Vec<PalletInfoData> {
+			vec![PalletInfoData {
+				index: 0,
+				name,
+				module_name: name,
+				crate_version: frame_support::traits::CrateVersion::new(1, 0, 0),
+			}]
+		}
+
+		pub fn current_era() -> u32 {
+			unhashed::get_or_default(&[&sp_io::hashing::twox_128(b"Staking")[..], ERA].concat())
+		}
+
+		/// The era at which the unbonded funds are unlocked, if not yet unlocked.
+		pub fn unlocking() -> Option<u32> {
+			unhashed::get(&[&sp_io::hashing::twox_128(b"Staking")[..], UNLOCKING].concat())
+		}
+
+		pub fn unbond() {
+			let key = [&sp_io::hashing::twox_128(b"Staking")[..], UNLOCKING].concat();
+			unhashed::put(&key, &(current_era() + BONDING_DURATION));
+		}
+
+		/// A toy staking pallet starting a new era at every era boundary.
+		pub struct Staking;
+
+		impl OnInitialize<u64> for Staking {
+			fn on_initialize(n: u64) -> Weight {
+				if n.checked_rem(EraLength::get()) == Some(0) {
+					let prefix = sp_io::hashing::twox_128(b"Staking");
+					let era = current_era() + 1;
+					unhashed::put(&[&prefix[..], ERA].concat(), &era);
+					if unlocking() == Some(era) {
+						unhashed::kill(&[&prefix[..], UNLOCKING].concat());
+					}
+				}
+				Weight::zero()
+			}
+		}
+
+		impl OnFinalize<u64> for Staking {}
+
+		impl PalletsInfoAccess for Staking {
+			fn infos() -> Vec<PalletInfoData> {
+				info("Staking")
+			}
+		}
+
+		/// A toy pallet counting the blocks in `on_initialize`.
+		pub struct Session;
+
+		impl OnInitialize<u64> for Session {
+			fn on_initialize(_: u64) -> Weight {
+				let key = [&sp_io::hashing::twox_128(SESSION)[..], b"blocks"].concat();
+				unhashed::put(&key, &(unhashed::get_or_default::<u64>(&key) + 1));
+				Weight::zero()
+			}
+		}
+
+		impl OnFinalize<u64> for Session {}
+
+		impl PalletsInfoAccess for Session {
+			fn infos() -> Vec<PalletInfoData> {
+				info("Session")
+			}
+		}
+
+		pub fn session_blocks() -> u64 {
+			unhashed::get_or_default(&[&sp_io::hashing::twox_128(SESSION)[..], b"blocks"].concat())
+		}
+	}
+
+	#[test]
+	fn time_travel_crosses_unbonding_period() {
+		use time_travel::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			unbond();
+			assert_eq!(unlocking(), Some(BONDING_DURATION));
+
+			let travel = TimeTravel::<Test, (Staking,)>::new().with_hooks_of::<(Staking,)>();
+			travel.jump_eras::<EraLength>(BONDING_DURATION - 1);
+			assert_eq!(current_era(), BONDING_DURATION - 1);
+			assert_eq!(unlocking(), Some(BONDING_DURATION));
+
+			travel.jump_eras::<EraLength>(1);
+			assert_eq!(current_era(), BONDING_DURATION);
+			assert_eq!(unlocking(), None);
+			assert_eq!(System::block_number(), u64::from(BONDING_DURATION) * EraLength::get());
+			assert_eq!(MockTime::get(), (System::block_number() - 1) * 6_000);
+		});
+	}
+
+	#[test]
+	fn time_travel_samples_block_hashes() {
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			TimeTravel::<Test, ()>::new().sample_hashes(100, 3).jump_to_block(1_000);
+			assert_eq!(System::block_number(), 1_000);
+
+			let recorded = crate::BlockHash::<Test>::iter_keys().collect::<BTreeSet<_>>();
+			let expected = [0, 100, 200, 300, 400, 500, 600, 700, 800, 900, 997, 998, 999];
+			assert_eq!(recorded, expected.into_iter().collect());
+			assert_eq!(System::parent_hash(), System::block_hash(999));
+
+			// Jumping backwards does nothing.
+			jump_to_block::<Test, ()>(10);
+			assert_eq!(System::block_number(), 1_000);
+		});
+	}
+
+	#[test]
+	fn time_travel_keeps_the_hash_of_the_current_block() {
+		new_test_ext().execute_with(|| {
+			System::set_block_number(5);
+			let hash = sp_core::H256::repeat_byte(5);
+			crate::BlockHash::<Test>::insert(5, hash);
+
+			jump_to_block::<Test, ()>(6);
+			assert_eq!(System::block_hash(5), hash);
+			assert_eq!(System::parent_hash(), hash);
+
+			jump_to_block::<Test, ()>(20);
+			assert_eq!(System::block_hash(5), hash);
+		});
+	}
+
+	#[test]
+	fn time_travel_replays_hooks_every_kth_block() {
+		use time_travel::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let travel = TimeTravel::<Test, (Session,)>::new()
+				.with_hooks_of::<(Session,)>()
+				.replay_every(10);
+			travel.jump_to_block(100);
+			// Blocks 10, 20, .., 90 and the target.
+			assert_eq!(session_blocks(), 10);
+			jump_eras::<Test, (), EraLength>(1);
+			assert_eq!(session_blocks(), 10);
+			assert_eq!(System::block_number(), EraLength::get());
+		});
+	}
+
+	#[test]
+	fn time_travel_detects_undeclared_hooks() {
+		use time_travel::*;
+
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			let travel =
+				TimeTravel::<Test, (Staking, Session)>::new().with_hooks_of::<(Staking,)>();
+			travel.jump_to_block(10);
+			assert_eq!(travel.undeclared_hooks(), vec!["Session"]);
+			// The calibration block is rolled back, and `Session` isn't replayed.
+			assert_eq!(session_blocks(), 0);
+
+			let travel = travel.with_hooks_of::<(Staking, Session)>();
+			travel.jump_to_block(20);
+			assert!(travel.undeclared_hooks().is_empty());
+			assert_eq!(session_blocks(), 1);
+		});
+	}
+
+	#[test]
+	fn mock_time_does_not_outlive_the_externalities() {
+		new_test_ext().execute_with(|| {
+			System::set_block_number(1);
+			jump_to_block::<Test, ()>(10);
+			assert_eq!(MockTime::get(), 9 * 6_000);
+		});
+
+		new_test_ext().execute_with(|| assert_eq!(MockTime::get(), 0));
+	}
+}
@@ -0,0 +1,401 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Provide types to help defining a mock environment when testing pallets.
+//!
+//! The helpers at the root of this module only need `alloc`, so they can also be used by tests
+//! compiled into a `no_std` runtime. The ones building on [`sp_io::TestExternalities`] are only
+//! available with the `std` feature.
+
+use crate::{
+	pallet_prelude::{BlockNumberFor, HeaderFor},
+	Config, RawOrigin,
+};
+use codec::{Decode, Encode};
+use sp_runtime::{
+	generic,
+	traits::{Hash, Header, TrailingZeroInput},
+	Digest, MultiAddress,
+};
+
+pub mod adversarial;
+#[cfg(feature = "std")]
+mod ext;
+#[cfg(feature = "std")]
+pub use ext::*;
+
+/// An unchecked extrinsic type to be used in tests.
+///
+/// The signer is addressed by `Address`, which has to be the `Source` of the `Lookup` of the
+/// runtime.
+pub type MockUncheckedExtrinsic<
+	T,
+	Signature = (),
+	Extra = (),
+	Address = <T as crate::Config>::AccountId,
+> = generic::UncheckedExtrinsic<Address, <T as crate::Config>::RuntimeCall, Signature, Extra>;
+
+/// An unchecked extrinsic type to be used in tests of runtimes whose `Lookup` is a
+/// `MultiAddress` lookup, like `AccountIdLookup<AccountId, AccountIndex>`.
+pub type MockMultiAddressExtrinsic<T, Signature = (), Extra = (), AccountIndex = ()> =
+	MockUncheckedExtrinsic<
+		T,
+		Signature,
+		Extra,
+		MultiAddress<<T as crate::Config>::AccountId, AccountIndex>,
+	>;
+
+/// An implementation of `sp_runtime::traits::Block` to be used in tests.
+///
+/// The headers are hashed with `Hashing`, e.g. `Keccak256` to match the hashes of an
+/// Ethereum-compatible chain:
+///
+/// ```
+/// # use frame_support::{construct_runtime, derive_impl};
+/// # use sp_runtime::traits::{Block as _, Hash, Header as _};
+/// type Block = frame_system::mocking::MockBlock<Test, sp_runtime::traits::Keccak256>;
+///
+/// # construct_runtime!(
+/// #     pub enum Test {
+/// #         System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+/// #     }
+/// # );
+/// #[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+/// impl frame_system::Config for Test {
+///     type Block = Block;
+///     type Hashing = sp_runtime::traits::Keccak256;
+///     // ...
+/// }
+///
+/// # fn main() {
+/// # let header = <Block as sp_runtime::traits::Block>::Header::new(
+/// #     1,
+/// #     Default::default(),
+/// #     Default::default(),
+/// #     Default::default(),
+/// #     Default::default(),
+/// # );
+/// # let block = Block::new(header.clone(), vec![]);
+/// assert_eq!(block.hash(), sp_runtime::traits::Keccak256::hash_of(&header));
+/// # }
+/// ```
+pub type MockBlock<T, Hashing = sp_runtime::traits::BlakeTwo256> =
+	generic::Block<MockHeaderU64<Hashing>, MockUncheckedExtrinsic<T>>;
+
+/// An implementation of `sp_runtime::traits::Block` to be used in tests with u32 BlockNumber type.
+///
+/// The block number of a runtime is the one of its `Block`, so a runtime using this block has
+/// u32 block numbers.
+pub type MockBlockU32<T, Hashing = sp_runtime::traits::BlakeTwo256> =
+	generic::Block<MockHeaderU32<Hashing>, MockUncheckedExtrinsic<T>>;
+
+/// An implementation of `sp_runtime::traits::Block` to be used in tests with u64 BlockNumber type.
+///
+/// This is the same as [`MockBlock`], for runtimes spelling out the width of their block numbers.
+pub type MockBlockU64<T, Hashing = sp_runtime::traits::BlakeTwo256> =
+	generic::Block<MockHeaderU64<Hashing>, MockUncheckedExtrinsic<T>>;
+
+/// An implementation of `sp_runtime::traits::Block` to be used in tests with u128 BlockNumber
+/// type.
+pub type MockBlockU128<T, Hashing = sp_runtime::traits::BlakeTwo256> =
+	generic::Block<MockHeaderU128<Hashing>, MockUncheckedExtrinsic<T>>;
+
+/// The header of [`MockBlockU32`].
+pub type MockHeaderU32<Hashing = sp_runtime::traits::BlakeTwo256> = generic::Header<u32, Hashing>;
+
+/// The header of [`MockBlock`] and [`MockBlockU64`].
+pub type MockHeaderU64<Hashing = sp_runtime::traits::BlakeTwo256> = generic::Header<u64, Hashing>;
+
+/// The header of [`MockBlockU128`].
+pub type MockHeaderU128<Hashing = sp_runtime::traits::BlakeTwo256> = generic::Header<u128, Hashing>;
+
+/// An implementation of `sp_runtime::traits::Block` to be used in tests of runtimes whose
+/// `Lookup` is a `MultiAddress` lookup, see [`MockMultiAddressExtrinsic`].
+pub type MockBlockMultiAddress<T, AccountIndex = (), Hashing = sp_runtime::traits::BlakeTwo256> =
+	generic::Block<
+		generic::Header<u64, Hashing>,
+		MockMultiAddressExtrinsic<T, (), (), AccountIndex>,
+	>;
+
+/// Generate the account `index` of the group `name`.
+///
+/// The account is derived deterministically, so the same arguments give the same account in
+/// every test.
+pub fn account<AccountId: Decode>(name: &'static str, index: u32) -> AccountId {
+	let entropy = (name, index).using_encoded(sp_io::hashing::blake2_256);
+	AccountId::decode(&mut TrailingZeroInput::new(&entropy[..]))
+		.expect("infinite length input; no invalid inputs for type; qed")
+}
+
+/// A signed origin of `who`.
+pub fn signed<T: Config>(who: T::AccountId) -> T::RuntimeOrigin {
+	RawOrigin::Signed(who).into()
+}
+
+/// The root origin.
+pub fn root<T: Config>() -> T::RuntimeOrigin {
+	RawOrigin::Root.into()
+}
+
+/// The none origin.
+pub fn none<T: Config>() -> T::RuntimeOrigin {
+	RawOrigin::None.into()
+}
+
+/// Build the header of block `number` on top of the block `parent_hash`, with empty roots.
+pub fn header<T: Config>(
+	number: impl Into<BlockNumberFor<T>>,
+	parent_hash: T::Hash,
+	digest: Digest,
+) -> HeaderFor<T> {
+	let empty_root = <HeaderFor<T> as Header>::Hashing::hash(&[]);
+	HeaderFor::<T>::new(number.into(), empty_root, empty_root, parent_hash, digest)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mock::{new_test_ext, SystemConfig, Test};
+	use sp_runtime::DigestItem;
+
+	#[test]
+	fn accounts_are_deterministic() {
+		assert_eq!(account::<u64>("caller", 0), account::<u64>("caller", 0));
+		assert_ne!(account::<u64>("caller", 0), account::<u64>("caller", 1));
+		assert_ne!(account::<u64>("caller", 0), account::<u64>("target", 0));
+	}
+
+	#[test]
+	fn origins_are_understood_by_ensure() {
+		let who = account::<u64>("caller", 0);
+		assert_eq!(crate::ensure_signed(signed::<Test>(who)).ok(), Some(who));
+		assert!(crate::ensure_root(root::<Test>()).is_ok());
+		assert!(crate::ensure_none(none::<Test>()).is_ok());
+	}
+
+	#[test]
+	fn header_can_be_imported() {
+		let digest = Digest { logs: vec![DigestItem::Other(b"test".to_vec())] };
+		let header = header::<Test>(1u32, [1u8; 32].into(), digest.clone());
+		assert_eq!(*header.number(), 1);
+		assert_eq!(header.digest(), &digest);
+
+		new_test_ext().execute_with(|| {
+			crate::Pallet::<Test>::initialize(
+				header.number(),
+				header.parent_hash(),
+				header.digest(),
+			);
+			assert_eq!(crate::Pallet::<Test>::block_number(), 1);
+			assert_eq!(crate::Pallet::<Test>::digest(), digest);
+		});
+	}
+
+	#[test]
+	fn std_helpers_are_reexported() {
+		// Existing paths into the `std` helpers keep working.
+		let _: crate::mocking::MockExternalitiesBuilder<Test> =
+			crate::mocking::MockExternalitiesBuilder::new();
+		drop(crate::mocking::serial_lock("reexport"));
+		let mut ext = crate::genesis! { Test; SystemConfig: default }.build();
+		ext.execute_with(|| assert!(crate::BlockHash::<Test>::contains_key(0)));
+	}
+
+	mod keccak {
+		use crate as frame_system;
+		use frame_support::derive_impl;
+		use sp_runtime::traits::Keccak256;
+
+		type Block = frame_system::mocking::MockBlock<Test, Keccak256>;
+
+		frame_support::construct_runtime!(
+			pub enum Test
+			{
+				System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+			}
+		);
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Test {
+			type Block = Block;
+			type Hashing = Keccak256;
+			type BlockHashCount = frame_support::traits::ConstU64<10>;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = PalletInfo;
+		}
+	}
+
+	#[test]
+	fn mock_block_can_use_another_hasher() {
+		use sp_runtime::traits::{Hash as _, Keccak256};
+
+		let keccak_header = header::<keccak::Test>(1u32, Default::default(), Default::default());
+		assert_eq!(keccak_header.hash(), Keccak256::hash_of(&keccak_header));
+		assert_eq!(*keccak_header.state_root(), Keccak256::hash(&[]));
+		assert_ne!(
+			keccak_header.hash(),
+			header::<Test>(1u32, Default::default(), Default::default()).hash()
+		);
+	}
+
+	mod multi_address {
+		use crate as frame_system;
+		use frame_support::derive_impl;
+		use sp_runtime::{
+			traits::{LookupError, StaticLookup},
+			MultiAddress,
+		};
+
+		type Block = frame_system::mocking::MockBlockMultiAddress<Test, u32>;
+
+		frame_support::construct_runtime!(
+			pub enum Test
+			{
+				System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+			}
+		);
+
+		/// Resolves the accounts like `AccountIdLookup`, and the index `i` to the account
+		/// `1_000 + i` for indices below 100.
+		pub struct IndexLookup;
+
+		impl StaticLookup for IndexLookup {
+			type Source = MultiAddress<u64, u32>;
+			type Target = u64;
+
+			fn lookup(address: Self::Source) -> Result<u64, LookupError> {
+				match address {
+					MultiAddress::Id(who) => Ok(who),
+					MultiAddress::Index(index) if index < 100 => Ok(1_000 + u64::from(index)),
+					_ => Err(LookupError),
+				}
+			}
+
+			fn unlookup(who: u64) -> Self::Source {
+				MultiAddress::Id(who)
+			}
+		}
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Test {
+			type Block = Block;
+			type Lookup = IndexLookup;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = PalletInfo;
+		}
+	}
+
+	#[test]
+	fn signed_extrinsics_can_be_addressed_by_index() {
+		use crate::{extensions::check_nonce::CheckNonce, ChainContext};
+		use multi_address::{RuntimeCall, Test};
+		use sp_runtime::{
+			generic::SignedPayload,
+			testing::TestSignature,
+			traits::{Applyable, Checkable},
+			transaction_validity::UnknownTransaction,
+			BuildStorage,
+		};
+
+		let signed = |address: MultiAddress<u64, u32>, who: u64| {
+			let call = RuntimeCall::System(crate::Call::remark { remark: vec![] });
+			let payload = SignedPayload::new(call, CheckNonce::<Test>::from(0)).unwrap();
+			let signature = TestSignature(who, payload.encode());
+			let (call, extra, _) = payload.deconstruct();
+			MockMultiAddressExtrinsic::<Test, _, _, u32>::new_signed(
+				call, address, signature, extra,
+			)
+		};
+
+		let mut ext: sp_io::TestExternalities =
+			crate::GenesisConfig::<Test>::default().build_storage().unwrap().into();
+		ext.execute_with(|| {
+			crate::Pallet::<Test>::inc_providers(&1_007);
+			let checked = signed(MultiAddress::Index(7), 1_007)
+				.check(&ChainContext::<Test>::default())
+				.unwrap();
+			assert_eq!(checked.signed.as_ref().map(|(who, _)| *who), Some(1_007));
+			let info = frame_support::dispatch::GetDispatchInfo::get_dispatch_info(&checked);
+			assert_eq!(checked.apply::<Test>(&info, 0), Ok(Ok(Default::default())));
+			assert_eq!(crate::Pallet::<Test>::account_nonce(1_007), 1);
+
+			// The signature is verified against the resolved account.
+			assert_eq!(
+				signed(MultiAddress::Index(7), 7).check(&ChainContext::<Test>::default()).err(),
+				Some(sp_runtime::transaction_validity::InvalidTransaction::BadProof.into())
+			);
+			// Unknown indices can't be looked up.
+			assert_eq!(
+				signed(MultiAddress::Index(100), 1_100)
+					.check(&ChainContext::<Test>::default())
+					.err(),
+				Some(UnknownTransaction::CannotLookup.into())
+			);
+		});
+	}
+
+	mod u128_blocks {
+		use crate as frame_system;
+		use frame_support::derive_impl;
+
+		type Block = frame_system::mocking::MockBlockU128<Test>;
+
+		frame_support::construct_runtime!(
+			pub enum Test
+			{
+				System: frame_system::{Pallet, Call, Config<T>, Storage, Event<T>},
+			}
+		);
+
+		#[derive_impl(frame_system::config_preludes::TestDefaultConfig as frame_system::DefaultConfig)]
+		impl frame_system::Config for Test {
+			type Block = Block;
+			type BlockHashCount = frame_support::traits::ConstU128<10>;
+			type RuntimeOrigin = RuntimeOrigin;
+			type RuntimeCall = RuntimeCall;
+			type RuntimeEvent = RuntimeEvent;
+			type PalletInfo = PalletInfo;
+		}
+	}
+
+	#[test]
+	fn mock_block_can_have_u128_block_numbers() {
+		use sp_runtime::BuildStorage;
+		use u128_blocks::Test;
+
+		// The block number of the runtime is the one of its block.
+		let _: fn(BlockNumberFor<Test>) -> u128 = |n| n;
+
+		let mut ext: sp_io::TestExternalities =
+			crate::GenesisConfig::<Test>::default().build_storage().unwrap().into();
+		ext.execute_with(|| {
+			let number = u128::from(u64::MAX) + 1;
+			let parent = header::<Test>(number - 1, Default::default(), Default::default());
+			crate::Pallet::<Test>::initialize(&number, &parent.hash(), &Default::default());
+			assert_eq!(crate::Pallet::<Test>::block_number(), number);
+			assert_eq!(crate::Pallet::<Test>::block_hash(number - 1), parent.hash());
+
+			let finalized = crate::Pallet::<Test>::finalize();
+			assert_eq!(*finalized.number(), number);
+			assert_eq!(*finalized.parent_hash(), parent.hash());
+		});
+	}
+}
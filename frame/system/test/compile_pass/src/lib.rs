@@ -0,0 +1,44 @@
+// This file is part of Substrate.
+
+// Copyright (C) Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: Apache-2.0
+
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// 	http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test that the `no_std` subset of `frame_system::mocking` compiles without the `std` feature.
+//!
+//! Build with `cargo build -p frame-system-test-compile-pass --no-default-features --target
+//! wasm32-unknown-unknown`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use frame_system::{
+	mocking::{self, MockBlock, MockBlockU128, MockBlockU32, MockUncheckedExtrinsic},
+	pallet_prelude::HeaderFor,
+	Config,
+};
+use sp_runtime::Digest;
+
+/// The block types can be named by a `no_std` runtime.
+pub type Block<T> = MockBlock<T>;
+pub type BlockU32<T> = MockBlockU32<T>;
+pub type BlockU128<T> = MockBlockU128<T>;
+pub type UncheckedExtrinsic<T> = MockUncheckedExtrinsic<T>;
+
+/// Uses the `no_std` helpers of `frame_system::mocking`.
+pub fn mock_values<T: Config>() -> (T::AccountId, [T::RuntimeOrigin; 3], HeaderFor<T>) {
+	let who = mocking::account::<T::AccountId>("caller", 0);
+	let origins = [mocking::signed::<T>(who.clone()), mocking::root::<T>(), mocking::none::<T>()];
+	let header = mocking::header::<T>(1u32, Default::default(), Digest::default());
+	(who, origins, header)
+}